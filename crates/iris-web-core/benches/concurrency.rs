@@ -0,0 +1,60 @@
+use std::{hint::black_box, thread, time::Duration};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use iris_web_core::prelude::*;
+
+/// Requests handled by every thread in a single iteration.
+const REQUESTS_PER_THREAD: u64 = 32;
+
+/// Controller doing a bit of work, so running it concurrently needs several cores to scale.
+fn compute(request: &Request) -> String {
+    let mut hash = 0u64;
+    for _ in 0..2_000 {
+        for byte in request.path.bytes() {
+            hash = black_box(hash.rotate_left(5) ^ byte as u64);
+        }
+    }
+    format!("{hash:x}")
+}
+
+/// Controller waiting like it would for a database, this scales even on a single core.
+fn wait() -> String {
+    thread::sleep(Duration::from_micros(200));
+    "Done".to_string()
+}
+
+/// Handles requests for the same route from several threads at once.
+fn concurrent_route(c: &mut Criterion) {
+    let mut router = Router::new();
+    router
+        .add_route("/compute", Method::GET, compute)
+        .add_route("/wait", Method::GET, wait);
+
+    let mut group = c.benchmark_group("concurrent_route");
+    for path in ["/compute", "/wait"] {
+        let raw = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        let request = Request::parse(&mut raw.as_bytes()).unwrap();
+
+        for threads in [1, 2, 4, 8] {
+            group.throughput(Throughput::Elements(threads * REQUESTS_PER_THREAD));
+            group.bench_with_input(BenchmarkId::new(path.trim_start_matches('/'), threads), &threads, |b, &threads| {
+                b.iter(|| {
+                    thread::scope(|scope| {
+                        for _ in 0..threads {
+                            scope.spawn(|| {
+                                for _ in 0..REQUESTS_PER_THREAD {
+                                    let (resolver, data) = router.resolve(&request.path).unwrap();
+                                    black_box(resolver.resolve(&request, data));
+                                }
+                            });
+                        }
+                    });
+                });
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, concurrent_route);
+criterion_main!(benches);
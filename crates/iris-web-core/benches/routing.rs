@@ -0,0 +1,48 @@
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use iris_web_core::prelude::*;
+
+/// Router with `size` routes spread over a few API versions, a third of them with placeholders.
+fn router(size: usize) -> Router {
+    let mut router = Router::new();
+    for i in 0..size / 3 {
+        let version = i % 4;
+        router
+            .add_route(&format!("/api/v{version}/resource{i}"), Method::GET, || "list")
+            .add_route(&format!("/api/v{version}/resource{i}/:id"), Method::GET, || "item")
+            .add_route(&format!("/api/v{version}/resource{i}/:id/items/:item"), Method::GET, || "nested");
+    }
+    router.compile();
+    router
+}
+
+/// Matches paths against routing tables of growing size.
+fn routing(c: &mut Criterion) {
+    let paths = [
+        ("static", "/api/v1/resource1"),
+        ("placeholder", "/api/v1/resource1/42"),
+        ("nested", "/api/v1/resource1/42/items/7"),
+        ("miss", "/api/v1/resource1/42/unknown"),
+    ];
+
+    let mut group = c.benchmark_group("routing");
+    for size in [300, 3_000, 30_000] {
+        let router = router(size);
+
+        for (name, path) in paths {
+            group.bench_with_input(BenchmarkId::new(format!("find/{name}"), size), &path, |b, path| {
+                b.iter(|| black_box(router.find(black_box(path)).map(|found| found.resolver() as *const _)))
+            });
+        }
+
+        // Resolving also builds the data of the request, including its path params.
+        group.bench_with_input(BenchmarkId::new("resolve/nested", size), &"/api/v1/resource1/42/items/7", |b, path| {
+            b.iter(|| black_box(router.resolve(black_box(path)).map(|(_, data)| data)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, routing);
+criterion_main!(benches);
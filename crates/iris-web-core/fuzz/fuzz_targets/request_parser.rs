@@ -0,0 +1,16 @@
+#![no_main]
+
+use iris_web_core::server::request::Request;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut reader = data;
+
+    // Keep parsing pipelined requests until the input is exhausted or invalid.
+    while let Ok(request) = Request::parse(&mut reader) {
+        // The query is split off the target, absolute-form targets are reduced to their path.
+        assert!(!request.path.contains('?'));
+        assert!(request.path.starts_with('/') || request.path == "*");
+        assert!(request.version.starts_with("HTTP/"));
+    }
+});
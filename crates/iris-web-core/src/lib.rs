@@ -1,28 +1,35 @@
-pub mod router;
-pub mod server;
-pub mod utils;
-pub mod pipeline;
-
-pub mod prelude {
-    // Server
-    pub use crate::server::http_server::HttpServer;
-
-    // Request
-    pub use crate::server::request::Request;
-    pub use crate::server::response::Response;
-    pub use crate::server::response::UnserializedBody;
-
-    // Router
-    pub use crate::router::router::{Router, Module};
-    pub use crate::router::PathParams;
-
-    // Data-related
-    pub use crate::pipeline::controller::Data;
-
-    // Methods
-    pub use crate::router::Method;
-
-    // Pipeline
-    pub use crate::pipeline::request_pipeline::PipelineData;
-    pub use crate::pipeline::controller::ConfigurableController;
+pub mod router;
+pub mod server;
+pub mod utils;
+pub mod pipeline;
+
+pub mod prelude {
+    // Server
+    pub use crate::server::http_server::HttpServer;
+    pub use crate::server::handle::ServerHandle;
+    pub use crate::server::shutdown::ShutdownHandle;
+    pub use crate::server::config::{ServerConfig, RequestLimits, PathNormalization};
+    #[cfg(feature = "tls")]
+    pub use crate::server::tls::{TlsConfig, TlsCertificate};
+
+    // Request
+    pub use crate::server::request::Request;
+    pub use crate::server::response::Response;
+    pub use crate::server::response::UnserializedBody;
+    pub use crate::server::response::StreamingBody;
+
+    // Router
+    pub use crate::router::router::{Router, Module, SharedRouter};
+    pub use crate::router::PathParams;
+    pub use crate::router::error::RouteError;
+
+    // Data-related
+    pub use crate::pipeline::controller::Data;
+
+    // Methods
+    pub use crate::router::{Method, Methods};
+
+    // Pipeline
+    pub use crate::pipeline::request_pipeline::PipelineData;
+    pub use crate::pipeline::controller::ConfigurableController;
 }
\ No newline at end of file
@@ -0,0 +1,269 @@
+use std::{future::Future, marker::PhantomData, pin::Pin, sync::Arc};
+
+use crate::server::response::{IntoResponse, Response};
+
+use super::{controller::{Controller, ControllerParam, IntoController}, middleware::{IntoMiddleware, MiddlewareHandler}, request_pipeline::PipelineData};
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// A controller that returns a future of the response.
+/// Async servers await it directly, sync ones block the worker until it completes.
+pub trait AsyncController: Send + Sync {
+    fn handle<'a>(&'a self, pipeline: &'a mut PipelineData) -> BoxFuture<'a, Response>;
+}
+
+/// A middleware that returns a future of its result.
+pub trait AsyncMiddlewareHandler: Send + Sync {
+    fn handle<'a>(&'a self, pipeline: &'a mut PipelineData) -> BoxFuture<'a, Option<Response>>;
+}
+
+/// Marker for the input of async controllers and middleware, so they don't overlap with sync ones.
+pub struct Async<Input>(PhantomData<fn() -> Input>);
+
+/// Function returning a future, implemented for all such functions.
+/// This allows naming the future of a function taking borrowed parameters.
+#[doc(hidden)]
+pub trait AsyncFunction<Args>: Send + Sync {
+    type Output;
+    type Future: Future<Output = Self::Output> + Send;
+
+    fn call(&self, args: Args) -> Self::Future;
+}
+
+/// Wrapper for an async function that can be used as a controller.
+pub struct AsyncFunctionController<Input, F> {
+    f: F,
+    marker: PhantomData<fn() -> Input>,
+}
+
+/// Wrapper for an async function that can be used as a middleware.
+pub struct AsyncFunctionMiddleware<Input, F> {
+    f: F,
+    marker: PhantomData<fn() -> Input>,
+}
+
+impl<T: AsyncController + 'static> Controller for Arc<T> {
+    fn handle(&self, pipeline: &mut PipelineData) -> Response {
+        block_on(AsyncController::handle(self.as_ref(), pipeline))
+    }
+
+    fn as_async(&self) -> Option<Arc<dyn AsyncController>> {
+        Some(self.clone())
+    }
+}
+
+impl<T: AsyncMiddlewareHandler + 'static> MiddlewareHandler for Arc<T> {
+    fn handle(&self, pipeline: &mut PipelineData) -> Option<Response> {
+        block_on(AsyncMiddlewareHandler::handle(self.as_ref(), pipeline))
+    }
+
+    fn as_async(&self) -> Option<Arc<dyn AsyncMiddlewareHandler>> {
+        Some(self.clone())
+    }
+}
+
+#[allow(non_snake_case, unused)]
+#[doc(hidden)]
+impl<R: IntoResponse, F> AsyncMiddlewareHandler for AsyncFunctionMiddleware<&mut PipelineData, F>
+where
+    F: for<'b> AsyncFunction<(&'b mut PipelineData,), Output = Option<R>>,
+{
+    fn handle<'a>(&'a self, pipeline: &'a mut PipelineData) -> BoxFuture<'a, Option<Response>> {
+        let future = AsyncFunction::<(&'a mut PipelineData,)>::call(&self.f, (pipeline,));
+        Box::pin(async move { future.await.map(|r| r.into_response()) })
+    }
+}
+
+#[allow(non_snake_case, unused)]
+#[doc(hidden)]
+impl<R: IntoResponse, F> IntoMiddleware<Async<&'static mut PipelineData>> for F
+where
+    F: for<'b> AsyncFunction<(&'b mut PipelineData,), Output = Option<R>> + 'static,
+{
+    type Middleware = Arc<AsyncFunctionMiddleware<&'static mut PipelineData, F>>;
+
+    fn into_middleware(self) -> Self::Middleware {
+        Arc::new(AsyncFunctionMiddleware {
+            f: self,
+            marker: PhantomData,
+        })
+    }
+}
+
+macro_rules! impl_async_function {
+    ($($params:ident),*) => {
+        #[allow(non_snake_case, unused)]
+        #[doc(hidden)]
+        impl<F, Fut, $($params),*> AsyncFunction<($($params,)*)> for F
+        where
+            F: Fn($($params),*) -> Fut + Send + Sync,
+            Fut: Future + Send,
+        {
+            type Output = Fut::Output;
+            type Future = Fut;
+
+            fn call(&self, ($($params,)*): ($($params,)*)) -> Self::Future {
+                (self)($($params),*)
+            }
+        }
+    };
+}
+
+macro_rules! impl_async_controller {
+    ($($params:ident),*) => {
+        #[allow(non_snake_case, unused)]
+        #[doc(hidden)]
+        impl<R: IntoResponse, F, $($params: ControllerParam),*> AsyncController for AsyncFunctionController<($($params,)*), F>
+        where
+            F: AsyncFunction<($($params,)*), Output = R>
+                + for<'b> AsyncFunction<($(<$params as ControllerParam>::Item<'b>,)*), Output = R>
+                + 'static,
+        {
+            fn handle<'a>(&'a self, pipeline: &'a mut PipelineData) -> BoxFuture<'a, Response> {
+                // Get the data from the request pipeline
+                $(
+                    let $params = $params::fetch(pipeline).unwrap();
+                )*
+
+                // Call the function
+                let future = AsyncFunction::<($(<$params as ControllerParam>::Item<'a>,)*)>::call(&self.f, ($($params,)*));
+                Box::pin(async move { future.await.into_response() })
+            }
+        }
+
+        #[allow(non_snake_case, unused)]
+        #[doc(hidden)]
+        impl<R: IntoResponse, F, $($params: ControllerParam + 'static),*> IntoController<Async<($($params,)*)>> for F
+        where
+            F: AsyncFunction<($($params,)*), Output = R>
+                + for<'b> AsyncFunction<($(<$params as ControllerParam>::Item<'b>,)*), Output = R>
+                + 'static,
+        {
+            type Controller = Arc<AsyncFunctionController<($($params,)*), F>>;
+
+            fn into_controller(self) -> Self::Controller {
+                Arc::new(AsyncFunctionController {
+                    f: self,
+                    marker: PhantomData,
+                })
+            }
+        }
+    };
+}
+
+macro_rules! impl_async_middleware {
+    ($($params:ident),*) => {
+        #[allow(non_snake_case, unused)]
+        #[doc(hidden)]
+        impl<R: IntoResponse, F, $($params: ControllerParam),*> AsyncMiddlewareHandler for AsyncFunctionMiddleware<($($params,)*), F>
+        where
+            F: AsyncFunction<($($params,)*), Output = Option<R>>
+                + for<'b> AsyncFunction<($(<$params as ControllerParam>::Item<'b>,)*), Output = Option<R>>
+                + 'static,
+        {
+            fn handle<'a>(&'a self, pipeline: &'a mut PipelineData) -> BoxFuture<'a, Option<Response>> {
+                // Get the data from the request pipeline
+                $(
+                    let $params = $params::fetch(pipeline).unwrap();
+                )*
+
+                // Call the function
+                let future = AsyncFunction::<($(<$params as ControllerParam>::Item<'a>,)*)>::call(&self.f, ($($params,)*));
+                Box::pin(async move { future.await.map(|r| r.into_response()) })
+            }
+        }
+
+        #[allow(non_snake_case, unused)]
+        #[doc(hidden)]
+        impl<R: IntoResponse, F, $($params: ControllerParam + 'static),*> IntoMiddleware<Async<($($params,)*)>> for F
+        where
+            F: AsyncFunction<($($params,)*), Output = Option<R>>
+                + for<'b> AsyncFunction<($(<$params as ControllerParam>::Item<'b>,)*), Output = Option<R>>
+                + 'static,
+        {
+            type Middleware = Arc<AsyncFunctionMiddleware<($($params,)*), F>>;
+
+            fn into_middleware(self) -> Self::Middleware {
+                Arc::new(AsyncFunctionMiddleware {
+                    f: self,
+                    marker: PhantomData,
+                })
+            }
+        }
+    };
+}
+
+impl_async_function!();
+impl_async_function!(A0);
+impl_async_function!(A0, A1);
+impl_async_function!(A0, A1, A2);
+impl_async_function!(A0, A1, A2, A3);
+
+impl_async_controller!();
+impl_async_controller!(A0);
+impl_async_controller!(A0, A1);
+impl_async_controller!(A0, A1, A2);
+impl_async_controller!(A0, A1, A2, A3);
+
+impl_async_middleware!();
+impl_async_middleware!(A0);
+impl_async_middleware!(A0, A1);
+impl_async_middleware!(A0, A1, A2);
+impl_async_middleware!(A0, A1, A2, A3);
+
+/// Runs the future to completion on the current thread.
+/// Used when async controllers are served by the sync server, each worker keeps its own
+/// runtime so futures using tokio timers or I/O work there too.
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    thread_local! {
+        static RUNTIME: tokio::runtime::Runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Failed to build the runtime of the worker");
+    }
+
+    RUNTIME.with(|runtime| runtime.block_on(future))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{pipeline::{controller::{ConfigurableController, Data}, request_pipeline::{IntoPipeline, PipelineData}}, server::request::Request, utils::data_container::DataContainer};
+
+    async fn greet(request: &Request, name: Data<'_, String>) -> String {
+        format!("{} {}", request.path, name.as_str())
+    }
+
+    async fn add_name(pipeline: &mut PipelineData) -> Option<String> {
+        pipeline.add_data("John".to_string());
+        None
+    }
+
+    async fn reject(request: &Request) -> Option<String> {
+        request.path.ends_with("forbidden").then(|| "Forbidden".to_string())
+    }
+
+    #[test]
+    fn test_async_pipeline() {
+        let pipeline = greet.with_middleware(add_name).with_middleware(reject).into_pipeline();
+
+        let request = Request { path: "/hello".to_string(), ..Default::default() };
+        let response = pipeline.handle(request, DataContainer::default());
+        assert_eq!(response.body, serde_json::to_vec("/hello John").unwrap());
+
+        let request = Request { path: "/forbidden".to_string(), ..Default::default() };
+        let response = pipeline.handle(request, DataContainer::default());
+        assert_eq!(response.body, serde_json::to_vec("Forbidden").unwrap());
+    }
+
+    #[test]
+    fn test_runtime_futures() {
+        async fn wait() -> String {
+            tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+            "Done".to_string()
+        }
+
+        // Tokio timers work when the sync server blocks on the controller.
+        let response = wait.into_pipeline().handle(Request::default(), DataContainer::default());
+        assert_eq!(response.body, serde_json::to_vec("Done").unwrap());
+    }
+}
@@ -1,7 +1,9 @@
-#![allow(clippy::needless_lifetimes)]
-
-pub mod request_pipeline;
-pub mod controller;
-pub mod function_controller;
-pub mod middleware;
-pub mod commands;
\ No newline at end of file
+#![allow(clippy::needless_lifetimes)]
+
+pub mod request_pipeline;
+pub mod controller;
+pub mod function_controller;
+pub mod middleware;
+pub mod commands;
+#[cfg(feature = "tokio")]
+pub mod async_controller;
\ No newline at end of file
@@ -1,88 +1,174 @@
-use std::{fmt::Debug, sync::Arc};
-
-use crate::{server::{request::Request, response::Response}, utils::data_container::DataContainer};
-
-use super::{controller::{Controller, IntoController}, commands::CommandQueue, middleware::BoxedMiddlewareHandler};
-
-pub(crate) type BoxedController = Box<dyn Controller + Send + Sync>;
-
-pub struct RequestPipeline {
-    pub(crate) middlewares: Vec<BoxedMiddlewareHandler>,
-    pub(crate) controller: BoxedController,
-}
-
-impl Debug for RequestPipeline {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("RequestPipeline")
-            .field("middleware count", &self.middlewares.len())
-            .finish()
-    }
-}
-
-impl RequestPipeline {
-    pub fn new(controller: BoxedController) -> Self {
-        Self {
-            middlewares: Vec::new(),
-            controller,
-        }
-    }
-
-    pub fn controller<I, C: Controller + Send + Sync + 'static>(controller: impl IntoController<I, Controller = C>) -> Self {
-        Self::new(Box::new(controller.into_controller()))
-    }
-
-    pub fn handle(&mut self, request: Request, data: DataContainer) -> Response {
-        let mut pipeline = PipelineData::new(request, data);
-
-        for middleware in &mut self.middlewares {
-            let r = middleware.handle(&mut pipeline);
-
-            // If the middleware returned a response, return it breaking the pipeline
-            if let Some(response) = r {
-                return response;
-            }
-
-            // Execute all commands in the queue
-            pipeline.command_queue.clone().execute(&mut pipeline);
-        }
-
-        self.controller.handle(&mut pipeline)   
-    }
-}
-
-pub struct PipelineData {
-    pub request: Request,
-    pub(crate) command_queue: Arc<CommandQueue>,
-    
-    pub(crate) data: DataContainer,
-}
-
-impl PipelineData {
-    pub fn new(request: Request, initial_data: DataContainer) -> Self {
-        Self {
-            request,
-            command_queue: Arc::new(CommandQueue::new()),
-            data: initial_data,
-        }
-    }
-
-    pub fn get<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
-        self.data.get::<T>()
-    }
-
-    pub fn add_data<T: Send + Sync + 'static>(&mut self, data: T) {
-        self.data.add(data);
-    }
-}
-
-pub trait IntoPipeline<M> {
-    fn into_pipeline(self) -> RequestPipeline;
-}
-
-impl<T, I, C: Controller + Send + Sync + 'static> IntoPipeline<(I, C)> for T
-    where T: IntoController<I, Controller = C>
-{
-    fn into_pipeline(self) -> RequestPipeline {
-        RequestPipeline::controller(self)
-    }
+use std::{fmt::Debug, sync::Arc};
+
+use crate::{server::{request::Request, response::Response, config::RequestLimits}, utils::data_container::DataContainer};
+#[cfg(feature = "tokio")]
+use crate::server::response::ResponseStatus;
+
+use super::{controller::{Controller, IntoController}, commands::CommandQueue, middleware::{BoxedMiddlewareHandler, RouterMiddlewares}};
+
+pub(crate) type BoxedController = Box<dyn Controller + Send + Sync>;
+
+pub struct RequestPipeline {
+    pub(crate) middlewares: Vec<BoxedMiddlewareHandler>,
+    pub(crate) controller: BoxedController,
+    /// Request limits overriding the ones of the router.
+    pub(crate) limits: Option<RequestLimits>,
+}
+
+impl Debug for RequestPipeline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RequestPipeline")
+            .field("middleware count", &self.middlewares.len())
+            .finish()
+    }
+}
+
+impl RequestPipeline {
+    pub fn new(controller: BoxedController) -> Self {
+        Self {
+            middlewares: Vec::new(),
+            controller,
+            limits: None,
+        }
+    }
+
+    pub fn controller<I, C: Controller + Send + Sync + 'static>(controller: impl IntoController<I, Controller = C>) -> Self {
+        Self::new(Box::new(controller.into_controller()))
+    }
+
+    pub fn handle(&self, request: Request, data: DataContainer) -> Response {
+        let routers = data.get::<RouterMiddlewares>();
+        let mut pipeline = PipelineData::new(request, data);
+
+        // The middleware of the routers runs first
+        let middlewares = routers.iter()
+            .flat_map(|routers| routers.0.iter().map(|middleware| middleware.as_ref()))
+            .chain(self.middlewares.iter().map(|middleware| middleware.as_ref()));
+
+        for middleware in middlewares {
+            let r = middleware.handle(&mut pipeline);
+
+            // If the middleware returned a response, return it breaking the pipeline
+            if let Some(response) = r {
+                return response;
+            }
+
+            // Execute all commands in the queue
+            pipeline.command_queue.clone().execute(&mut pipeline);
+        }
+
+        self.controller.handle(&mut pipeline)   
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl RequestPipeline {
+    /// Runs the pipeline on the tokio runtime. Async middleware and controllers are awaited,
+    /// sync ones run on the blocking pool so they don't stall other requests.
+    pub(crate) async fn handle_async(pipeline: Arc<RequestPipeline>, request: Request, data: DataContainer) -> Response {
+        let routers = data.get::<RouterMiddlewares>().map(|routers| routers.0.clone()).unwrap_or_default();
+        let middlewares = routers.iter()
+            .map(|middleware| middleware.as_async())
+            .chain(pipeline.middlewares.iter().map(|middleware| middleware.as_async()))
+            .collect::<Vec<_>>();
+        let controller = pipeline.controller.as_async();
+
+        // Without async parts the whole pipeline runs in one go.
+        if controller.is_none() && middlewares.iter().all(Option::is_none) {
+            let handled = tokio::task::spawn_blocking(move || pipeline.handle(request, data));
+            return handled.await.unwrap_or_else(|_| Response::new().with_status(ResponseStatus::InternalServerError));
+        }
+
+        let mut pipeline_data = PipelineData::new(request, data);
+
+        for (index, middleware) in middlewares.into_iter().enumerate() {
+            let r = match middleware {
+                Some(middleware) => middleware.handle(&mut pipeline_data).await,
+                None => {
+                    let router = routers.get(index).cloned();
+                    let own = index.saturating_sub(routers.len());
+                    let handled = Self::run_blocking(&pipeline, pipeline_data, move |pipeline, data| match router {
+                        Some(middleware) => middleware.handle(data),
+                        None => pipeline.middlewares[own].handle(data),
+                    }).await;
+
+                    match handled {
+                        Ok((r, data)) => {
+                            pipeline_data = data;
+                            r
+                        }
+                        Err(response) => return response,
+                    }
+                }
+            };
+
+            // If the middleware returned a response, return it breaking the pipeline
+            if let Some(response) = r {
+                return response;
+            }
+
+            // Execute all commands in the queue
+            pipeline_data.command_queue.clone().execute(&mut pipeline_data);
+        }
+
+        match controller {
+            Some(controller) => controller.handle(&mut pipeline_data).await,
+            None => Self::run_blocking(&pipeline, pipeline_data, |pipeline, data| pipeline.controller.handle(data))
+                .await
+                .map(|(response, _)| response)
+                .unwrap_or_else(|response| response),
+        }
+    }
+
+    /// Runs a sync part of the pipeline on the blocking pool, a panic is turned into an error response.
+    async fn run_blocking<T: Send + 'static>(
+        pipeline: &Arc<RequestPipeline>,
+        mut pipeline_data: PipelineData,
+        f: impl FnOnce(&RequestPipeline, &mut PipelineData) -> T + Send + 'static,
+    ) -> Result<(T, PipelineData), Response> {
+        let pipeline = pipeline.clone();
+        let handled = tokio::task::spawn_blocking(move || {
+            let r = f(&pipeline, &mut pipeline_data);
+            (r, pipeline_data)
+        });
+
+        handled.await.map_err(|_| Response::new().with_status(ResponseStatus::InternalServerError))
+    }
+}
+
+pub struct PipelineData {
+    pub request: Request,
+    pub(crate) command_queue: Arc<CommandQueue>,
+    
+    pub(crate) data: DataContainer,
+}
+
+impl PipelineData {
+    pub fn new(request: Request, initial_data: DataContainer) -> Self {
+        Self {
+            request,
+            command_queue: Arc::new(CommandQueue::new()),
+            data: initial_data,
+        }
+    }
+
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        self.data.get::<T>()
+    }
+
+    pub fn add_data<T: Send + Sync + 'static>(&mut self, data: T) {
+        self.data.add(data);
+    }
+}
+
+pub trait IntoPipeline<M> {
+    fn into_pipeline(self) -> RequestPipeline;
+}
+
+impl<T, I, C: Controller + Send + Sync + 'static> IntoPipeline<(I, C)> for T
+    where T: IntoController<I, Controller = C>
+{
+    fn into_pipeline(self) -> RequestPipeline {
+        RequestPipeline::controller(self)
+    }
 }
\ No newline at end of file
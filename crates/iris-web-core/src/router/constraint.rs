@@ -0,0 +1,125 @@
+use std::{any::Any, collections::HashMap, str::FromStr, sync::Arc};
+
+/// Value of a path parameter parsed by its constraint.
+pub(crate) type ParsedValue = Arc<dyn Any + Send + Sync>;
+
+/// Constraint of a placeholder, written after its name like `:id<u64>`.
+///
+/// It is either a type that the segment has to parse as, the name of a predicate
+/// added with `Router::add_constraint`, or a regular expression the whole segment has to match.
+#[derive(Clone)]
+pub(crate) struct Constraint {
+    source: Box<str>,
+    kind: ConstraintKind,
+}
+
+#[derive(Clone)]
+enum ConstraintKind {
+    Type(fn(&str) -> Option<ParsedValue>),
+    Predicate(Predicate),
+    #[cfg(feature = "regex")]
+    Regex(regex::Regex),
+}
+
+/// Custom constraint added to a router.
+pub(crate) type Predicate = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
+fn parse<T: FromStr + Send + Sync + 'static>(value: &str) -> Option<ParsedValue> {
+    value.parse::<T>().ok().map(|value| Arc::new(value) as ParsedValue)
+}
+
+fn builtin(name: &str) -> Option<fn(&str) -> Option<ParsedValue>> {
+    Some(match name {
+        "u8" => parse::<u8>,
+        "u16" => parse::<u16>,
+        "u32" => parse::<u32>,
+        "u64" => parse::<u64>,
+        "u128" => parse::<u128>,
+        "usize" => parse::<usize>,
+        "i8" => parse::<i8>,
+        "i16" => parse::<i16>,
+        "i32" => parse::<i32>,
+        "i64" => parse::<i64>,
+        "i128" => parse::<i128>,
+        "isize" => parse::<isize>,
+        "f32" => parse::<f32>,
+        "f64" => parse::<f64>,
+        "bool" => parse::<bool>,
+        _ => return None,
+    })
+}
+
+/// Splits a placeholder like `id<u64>` into its name and the source of its constraint.
+pub(crate) fn split_placeholder(placeholder: &str) -> (&str, Option<&str>) {
+    match placeholder.strip_suffix('>').and_then(|rest| rest.split_once('<')) {
+        Some((name, source)) => (name, Some(source)),
+        None => (placeholder, None),
+    }
+}
+
+impl Constraint {
+    /// Parses the constraint, names are looked up in the types and then in the predicates of the router.
+    /// Anything else is a regular expression.
+    pub(crate) fn parse(source: &str, predicates: &HashMap<String, Predicate>) -> Result<Self, &'static str> {
+        let is_name = !source.is_empty() && source.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+        let kind = if let Some(parse) = builtin(source) {
+            ConstraintKind::Type(parse)
+        } else if let Some(predicate) = predicates.get(source) {
+            ConstraintKind::Predicate(predicate.clone())
+        } else if is_name {
+            return Err("the constraint is neither a type nor added to the router");
+        } else {
+            Self::regex(source)?
+        };
+
+        Ok(Self { source: source.into(), kind })
+    }
+
+    #[cfg(feature = "regex")]
+    fn regex(source: &str) -> Result<ConstraintKind, &'static str> {
+        regex::Regex::new(&format!("^(?:{source})$"))
+            .map(ConstraintKind::Regex)
+            .map_err(|_| "the constraint is not a valid regular expression")
+    }
+
+    #[cfg(not(feature = "regex"))]
+    fn regex(_source: &str) -> Result<ConstraintKind, &'static str> {
+        Err("regular expression constraints need the `regex` feature")
+    }
+
+    /// Returns the constraint as it is written in the route.
+    pub(crate) fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Returns whether the segment satisfies the constraint.
+    pub(crate) fn matches(&self, segment: &str) -> bool {
+        match &self.kind {
+            ConstraintKind::Type(parse) => parse(segment).is_some(),
+            ConstraintKind::Predicate(predicate) => predicate(segment),
+            #[cfg(feature = "regex")]
+            ConstraintKind::Regex(regex) => regex.is_match(segment),
+        }
+    }
+
+    /// Returns the segment parsed as the type of the constraint, if it has one.
+    pub(crate) fn parse_value(&self, segment: &str) -> Option<ParsedValue> {
+        match &self.kind {
+            ConstraintKind::Type(parse) => parse(segment),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Debug for Constraint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<{}>", self.source)
+    }
+}
+
+impl PartialEq for Constraint {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
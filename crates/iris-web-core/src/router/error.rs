@@ -0,0 +1,45 @@
+/// Conflict between routes, found when a route is registered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RouteError {
+    /// The method is already handled by a route with the same path.
+    DuplicateRoute { method: String, path: String },
+    /// The route only differs from an existing one in the names of its placeholders,
+    /// so it would be unclear which names apply.
+    AmbiguousPlaceholder { path: String, existing: String },
+    /// The route can never be matched, because `by` takes its place.
+    UnreachableRoute { route: String, by: String },
+    /// The path of the route can't be matched as it is written.
+    InvalidPath { path: String, reason: &'static str },
+}
+
+impl RouteError {
+    /// Returns the error with the paths prefixed, used when a router is mounted as a module.
+    pub(crate) fn prefixed(self, prefix: &str) -> Self {
+        // Descriptions of routes start with their methods.
+        let join = |route: String| match route.find('/') {
+            Some(start) if prefix.is_empty() || route[start..] != *"/" => format!("{}{prefix}{}", &route[..start], &route[start..]),
+            Some(start) => format!("{}{prefix}", &route[..start]),
+            None => route,
+        };
+
+        match self {
+            RouteError::DuplicateRoute { method, path } => RouteError::DuplicateRoute { method, path: join(path) },
+            RouteError::AmbiguousPlaceholder { path, existing } => RouteError::AmbiguousPlaceholder { path: join(path), existing: join(existing) },
+            RouteError::UnreachableRoute { route, by } => RouteError::UnreachableRoute { route: join(route), by: join(by) },
+            RouteError::InvalidPath { path, reason } => RouteError::InvalidPath { path: join(path), reason },
+        }
+    }
+}
+
+impl std::fmt::Display for RouteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RouteError::DuplicateRoute { method, path } => write!(f, "Route {method} {path} is registered twice"),
+            RouteError::AmbiguousPlaceholder { path, existing } => write!(f, "Route {path} conflicts with {existing}, placeholders at the same position of a route must have the same name"),
+            RouteError::UnreachableRoute { route, by } => write!(f, "Route {route} is unreachable, {by} takes its place"),
+            RouteError::InvalidPath { path, reason } => write!(f, "Route {path} is invalid, {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for RouteError {}
@@ -1,65 +1,171 @@
-use std::{collections::HashMap, sync::Mutex, fmt::Debug};
-
-#[allow(clippy::module_inception)]
-pub mod router;
-
-pub enum Method {
-    GET,
-    POST,
-    PUT,
-    DELETE,
-    PATCH,
-    HEAD,
-    OPTIONS,
-    CONNECT,
-    TRACE,
-}
-
-impl Method {
-    pub fn as_str(&self) -> String {
-        match self {
-            Method::GET => "GET".to_string(),
-            Method::POST => "POST".to_string(),
-            Method::PUT => "PUT".to_string(),
-            Method::DELETE => "DELETE".to_string(),
-            Method::PATCH => "PATCH".to_string(),
-            Method::HEAD => "HEAD".to_string(),
-            Method::OPTIONS => "OPTIONS".to_string(),
-            Method::CONNECT => "CONNECT".to_string(),
-            Method::TRACE => "TRACE".to_string(),
-        }
-    }
-}
-
-pub struct PathParams {
-    #[doc(hidden)]
-    pub params: Mutex<HashMap<String, String>>,
-}
-
-impl Debug for PathParams {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("PathParams")
-            .field("params", &self.params.lock().unwrap())
-            .finish()
-    }
-}
-
-impl PathParams {
-    pub(crate) fn new() -> Self {
-        Self {
-            params: Mutex::new(HashMap::new()),
-        }
-    }
-
-    pub(crate) fn add_param(&self, key: String, value: String) {
-        self.params.lock().unwrap().insert(key, value);
-    }
-
-    pub fn get_param(&self, key: &str) -> Option<String> {
-        self.params.lock().unwrap().get(key).cloned()
-    }
-
-    pub fn get_params(&self) -> HashMap<String, String> {
-        self.params.lock().unwrap().clone()
-    }
+use std::{collections::HashMap, sync::Mutex, fmt::{Debug, Display}, hash::{Hash, Hasher}, str::FromStr};
+
+use constraint::ParsedValue;
+
+#[allow(clippy::module_inception)]
+pub mod router;
+pub mod tree;
+pub mod error;
+pub(crate) mod constraint;
+
+/// Method of a request. Methods other than the standard ones, like the WebDAV `PROPFIND`
+/// or `PURGE`, are extension methods. Methods are case-sensitive.
+#[derive(Clone, Debug, Default)]
+pub enum Method {
+    #[default]
+    GET,
+    POST,
+    PUT,
+    DELETE,
+    PATCH,
+    HEAD,
+    OPTIONS,
+    CONNECT,
+    TRACE,
+    Extension(Box<str>),
+}
+
+impl Method {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Method::GET => "GET",
+            Method::POST => "POST",
+            Method::PUT => "PUT",
+            Method::DELETE => "DELETE",
+            Method::PATCH => "PATCH",
+            Method::HEAD => "HEAD",
+            Method::OPTIONS => "OPTIONS",
+            Method::CONNECT => "CONNECT",
+            Method::TRACE => "TRACE",
+            Method::Extension(method) => method,
+        }
+    }
+}
+
+/// Error returned when a method is not a valid HTTP token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidMethod;
+
+impl Display for InvalidMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid HTTP method")
+    }
+}
+
+impl std::error::Error for InvalidMethod {}
+
+impl FromStr for Method {
+    type Err = InvalidMethod;
+
+    fn from_str(method: &str) -> Result<Self, Self::Err> {
+        Ok(match method {
+            "GET" => Method::GET,
+            "POST" => Method::POST,
+            "PUT" => Method::PUT,
+            "DELETE" => Method::DELETE,
+            "PATCH" => Method::PATCH,
+            "HEAD" => Method::HEAD,
+            "OPTIONS" => Method::OPTIONS,
+            "CONNECT" => Method::CONNECT,
+            "TRACE" => Method::TRACE,
+            _ if !method.is_empty() && method.bytes().all(is_token) => Method::Extension(method.into()),
+            _ => return Err(InvalidMethod),
+        })
+    }
+}
+
+/// Returns whether the byte may be part of a token, like a method or a header name.
+pub(crate) fn is_token(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+}
+
+impl Display for Method {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// Compared by name, so an extension method spelled like a standard one is the same method.
+impl PartialEq for Method {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for Method {}
+
+impl Hash for Method {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state);
+    }
+}
+
+/// Methods a route is added for, a single method, several of them or any method.
+/// Pipelines added for a method take precedence over the one for any method.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Methods {
+    Only(Vec<Method>),
+    Any,
+}
+
+impl From<Method> for Methods {
+    fn from(method: Method) -> Self {
+        Methods::Only(vec![method])
+    }
+}
+
+impl<const N: usize> From<[Method; N]> for Methods {
+    fn from(methods: [Method; N]) -> Self {
+        Methods::Only(methods.into())
+    }
+}
+
+impl From<Vec<Method>> for Methods {
+    fn from(methods: Vec<Method>) -> Self {
+        Methods::Only(methods)
+    }
+}
+
+pub struct PathParams {
+    #[doc(hidden)]
+    pub params: Mutex<HashMap<String, String>>,
+    parsed: Mutex<HashMap<String, ParsedValue>>,
+}
+
+impl Debug for PathParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PathParams")
+            .field("params", &self.params.lock().unwrap())
+            .finish()
+    }
+}
+
+impl PathParams {
+    pub(crate) fn new() -> Self {
+        Self {
+            params: Mutex::new(HashMap::new()),
+            parsed: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn add_param(&self, key: String, value: String) {
+        self.params.lock().unwrap().insert(key, value);
+    }
+
+    pub(crate) fn add_parsed(&self, key: String, value: ParsedValue) {
+        self.parsed.lock().unwrap().insert(key, value);
+    }
+
+    /// Returns the value of a placeholder with a type constraint like `:id<u64>`, parsed when the route matched.
+    pub fn get_parsed<T: Clone + 'static>(&self, key: &str) -> Option<T> {
+        self.parsed.lock().unwrap().get(key)?.downcast_ref::<T>().cloned()
+    }
+
+    pub fn get_param(&self, key: &str) -> Option<String> {
+        self.params.lock().unwrap().get(key).cloned()
+    }
+
+    pub fn get_params(&self) -> HashMap<String, String> {
+        self.params.lock().unwrap().clone()
+    }
 }
\ No newline at end of file
@@ -1,326 +1,1151 @@
-use std::{collections::HashMap, sync::Mutex};
-
-use crate::{server::{request::Request, response::{Response, ResponseStatus}}, pipeline::request_pipeline::{RequestPipeline, IntoPipeline}, utils::data_container::DataContainer};
-
-use super::{Method, PathParams};
-
-/// A router is a collection of routes that can be used to match a path.
-#[derive(Default)]
-pub struct Router {
-    /// The routes that are registered with this router.
-    pub(crate) routes: HashMap<String, PathResolver>,
-    /// The fallback route that is used when no other route matches.
-    pub(crate) fallback: Option<PathResolver>,
-    pub(crate) fallback_name: Option<String>,
-
-    /// Data that is shared between all routes in this router.
-    pub(crate) data: DataContainer,
-}
-
-impl Router {
-    /// Creates a new router.
-    pub fn new() -> Self {
-        Self {
-            routes: HashMap::new(),
-            fallback: None,
-            fallback_name: None,
-            data: DataContainer::default(),
-        }
-    }
-
-    /// Adds a new route to the router. This is a convenience method for adding a route to the router.
-    pub fn add_route<T>(&mut self, path: &str, method: Method, controller: impl IntoPipeline<T>) -> &mut Self {
-        self.add_pipeline(path, method, controller.into_pipeline());
-        self
-    }
-
-    /// Adds new module to the router. This is a convenience method for adding a module to the router.
-    pub fn add_module(&mut self, path: &str, module: impl Module) -> &mut Self {
-        let mut router = Router::new();
-
-        module.build(&mut router);
-
-        self.insert(path, PathResolver::Router(Box::new(router)));
-
-        self
-    }
-
-    /// Adds data to the scoped data container.
-    pub fn add_data<T: Send + Sync + 'static>(&mut self, value: T) -> &mut Self {
-        self.data.add(value);
-        self
-    }
-
-    pub(crate) fn add_pipeline(&mut self, path: &str, method: Method, pipeline: RequestPipeline) {
-        // Get the resolver or create a new one.
-        let resolver = match self.resolve_internal_mut(path, DataContainer::default()) {
-            Some(resolver) => resolver,
-            None => {
-                let mut pipeline_map = HashMap::new();
-                pipeline_map.insert(method.as_str(), Mutex::new(pipeline));
-                self.insert(path, PathResolver::Pipeline(pipeline_map));
-                return;
-            }
-        };
-
-        // Add the pipeline to the resolver.
-        #[allow(clippy::single_match)]
-        match resolver.0 {
-            PathResolver::Pipeline(pipelines) => {
-                pipelines.insert(method.as_str(), Mutex::new(pipeline));
-            }
-            _ => {}
-        }
-
-        #[cfg(debug_assertions)]
-        println!("{self:#?}");
-    }
-
-    /// Inserts a new route into the router creating sub-routers as needed.
-    /// :id can be used like a placeholder to match any path segment.
-    pub(crate) fn insert(&mut self, path: &str, resolver: PathResolver) {
-        // Special case for root path.
-        if path.trim() == "/" {
-            self.routes.insert("".to_string(), resolver);
-            return;
-        }
-
-        let mut segments = path.split('/').filter(|s| !s.is_empty());
-
-        // Get the first segment of the path.
-        let segment = match segments.next() {
-            Some(segment) => segment,
-            None => return,
-        };
-
-        // Get the rest of the path.
-        let rest = segments.collect::<Vec<_>>().join("/");
-
-        // If rest is empty, then insert the resolver directly.
-        if rest.is_empty() {
-            // Placeholder
-            if segment.starts_with(':') {
-                // Insert as fallback.
-                self.fallback = Some(resolver);
-                self.fallback_name = Some(segment.strip_prefix(':').unwrap().to_string());
-            } else {
-                self.routes.insert(segment.to_string(), resolver);
-            }
-            return;
-        }
-
-        // If the segment is a placeholder, then insert new router as fallback.
-        if segment.starts_with(':') {
-            // Check if there is already a fallback.
-            match self.fallback {
-                Some(PathResolver::Router(_)) => {}
-                Some(_) => {
-                    // Create a new router and insert the existing resolver as an "" route.
-                    let mut router = Router::new();
-                    router.routes.insert("".to_string(), self.fallback.take().unwrap());
-                    router.insert(&rest, resolver);
-                    self.fallback = Some(PathResolver::Router(Box::new(router)));
-                    self.fallback_name = Some(segment.strip_prefix(':').unwrap().to_string());
-                }
-                None => {
-                    // Create a new router.
-                    let mut router = Router::new();
-                    router.insert(&rest, resolver);
-                    self.fallback = Some(PathResolver::Router(Box::new(router)));
-                    self.fallback_name = Some(segment.strip_prefix(':').unwrap().to_string());
-                }
-            }
-        } else {
-            let sub_router = match self.routes.get_mut(segment) {
-                Some(PathResolver::Router(router)) => router,
-                Some(_) => {
-                    // Create a new router and insert the existing resolver as an "" route.
-                    let mut router = Router::new();
-                    router.routes.insert("".to_string(), self.routes.remove(segment).unwrap());
-                    self.routes.insert(segment.to_string(), PathResolver::Router(Box::new(router)));
-                    match self.routes.get_mut(segment) {
-                        Some(PathResolver::Router(router)) => router,
-                        _ => unreachable!(),
-                    }
-                }
-                None => {
-                    // Create a new router.
-                    let router = Router::new();
-                    self.routes.insert(segment.to_string(), PathResolver::Router(Box::new(router)));
-                    match self.routes.get_mut(segment) {
-                        Some(PathResolver::Router(router)) => router,
-                        _ => unreachable!(),
-                    }
-                }
-            };
-
-            sub_router.insert(&rest, resolver);
-        }
-    }
-
-    /// Finds a route that matches the given path and returns the resolver.
-    /// :id can be used like a placeholder to match any path segment.
-    pub fn resolve(&self, path: &str) -> Option<(&PathResolver, DataContainer)> {
-        self.resolve_internal(path, DataContainer::default())
-    } 
-
-    #[doc(hidden)]
-    fn add_path_param(&self, param: &str, value: &str, data: &mut DataContainer) {
-        if let Some(params) = data.get::<PathParams>() {
-            params.add_param(param.to_string(), value.to_string())
-        } else {
-            let params = PathParams::new();
-            params.add_param(param.to_string(), value.to_string());
-            data.add(params);
-        }
-    }
-    
-    #[doc(hidden)]
-    fn resolve_internal(&self, path: &str, current_data: DataContainer) -> Option<(&PathResolver, DataContainer)> {
-        let mut segments = path.split('/').filter(|s| !s.is_empty());
-
-        let mut data = current_data.combine(&self.data);
-
-        // Get the first segment of the path.
-        let segment = segments.next().unwrap_or("");
-
-        // Get the rest of the path.
-        let rest = segments.collect::<Vec<_>>().join("/");
-
-        if let Some(resolver) = self.routes.get(segment) {
-            match resolver {
-                PathResolver::Router(ref router) => router.resolve_internal(&rest, data),
-                _ => Some((resolver, data)),
-            }
-        } else {
-            if self.fallback.is_some() && self.fallback_name.is_some() {
-                // Insert the segment as a path param.
-                self.add_path_param(self.fallback_name.as_ref().unwrap(), segment, &mut data);
-            }
-
-            match self.fallback {
-                Some(PathResolver::Router(ref router)) => router.resolve_internal(&rest, data),
-                Some(_) => {
-                    if rest.is_empty() {
-                        self.fallback.as_ref().map(|r| (r, data))
-                    } else {
-                        None
-                    }
-                }
-                None => None,
-            }
-        }
-    }
-    
-    #[doc(hidden)]
-    fn resolve_internal_mut(&mut self, path: &str, current_data: DataContainer) -> Option<(&mut PathResolver, DataContainer)> {
-        let mut segments = path.split('/').filter(|s| !s.is_empty());
-
-        let data = current_data.combine(&self.data);
-
-        // Get the first segment of the path.
-        let segment = segments.next().unwrap_or("");
-
-        // Get the rest of the path.
-        let rest = segments.collect::<Vec<_>>().join("/");
-
-        if let Some(resolver) = self.routes.get_mut(segment) {
-            match resolver {
-                PathResolver::Router(ref mut router) => router.resolve_internal_mut(&rest, data),
-                _ => Some((resolver, data)),
-            }
-        } else {
-            match self.fallback {
-                Some(PathResolver::Router(ref mut router)) => router.resolve_internal_mut(&rest, data),
-                Some(_) => {
-                    if rest.is_empty() {
-                        self.fallback.as_mut().map(|r| (r, data))
-                    } else {
-                        None
-                    }
-                }
-                None => None,
-            }
-        }
-    }
-}
-
-// Debug impl
-impl std::fmt::Debug for Router {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Router")
-            .field("routes", &self.routes)
-            .field("fallback", &self.fallback)
-            .finish()
-    }
-}
-
-#[derive(Debug)]
-/// Enum that represents all possible ways to resolve a path.
-pub enum PathResolver {
-    Router(Box<Router>),
-    Placeholder(String),
-    Pipeline(HashMap<String, Mutex<RequestPipeline>>),
-}
-
-impl PartialEq for PathResolver {
-    fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (PathResolver::Placeholder(a), PathResolver::Placeholder(b)) => a == b,
-            _ => false,
-        }
-    }
-}
-
-impl PathResolver {
-    /// Returns new response based on the request
-    pub fn resolve(&self, request: &Request, data: DataContainer) -> Response {
-        match self {
-            PathResolver::Placeholder(data) => Response::new().with_status(ResponseStatus::Ok).with_body(data.clone().into_bytes()),
-            PathResolver::Pipeline(pipeline) => {
-                // Get the method
-                let method = &request.method;
-
-                // Get the pipeline
-                let pipeline = match pipeline.get(method) {
-                    Some(pipeline) => pipeline,
-                    None => return Response::new().with_status(ResponseStatus::MethodNotAllowed)
-                };
-
-                // Get the pipeline
-                let mut pipeline = pipeline.lock().unwrap();
-
-                // Resolve the pipeline
-                pipeline.handle(request.clone(), data)
-            }
-            _ => Response::new().with_status(ResponseStatus::InternalServerError),
-        }
-    }
-}
-
-pub trait Module {
-    fn build(self, router: &mut Router);
-}
-
-#[cfg(test)]
-mod test {
-    use super::*;
-
-    #[test]
-    fn test_router() {
-        let mut router = Router::new();
-
-        router.insert("/", PathResolver::Placeholder("Root".to_string()));
-        router.insert("/hello/world", PathResolver::Placeholder("Hello World".to_string()));
-        router.insert("/hello/world/test", PathResolver::Placeholder("Hello World test".to_string()));
-
-        router.insert("/hello/:name", PathResolver::Placeholder("Hello Name".to_string()));
-        router.insert("/hello/:name/:age", PathResolver::Placeholder("Hello Name Age".to_string()));
-
-        assert_eq!(router.resolve("/").unwrap().0, &PathResolver::Placeholder("Root".to_string()));
-        assert_eq!(router.resolve("/hello/world").unwrap().0, &PathResolver::Placeholder("Hello World".to_string()));
-        assert_eq!(router.resolve("/hello/world/test").unwrap().0, &PathResolver::Placeholder("Hello World test".to_string()));
-        assert_eq!(router.resolve("/hello/John").unwrap().0, &PathResolver::Placeholder("Hello Name".to_string()));
-        assert_eq!(router.resolve("/hello/John/20").unwrap().0, &PathResolver::Placeholder("Hello Name Age".to_string()));
-    }
-}
\ No newline at end of file
+use std::{collections::HashMap, sync::{Arc, OnceLock, RwLock}};
+
+use crate::{server::{request::Request, response::{Response, ResponseStatus}, config::RequestLimits}, pipeline::{middleware::{IntoMiddleware, MiddlewareHandler, SharedMiddlewareHandler}, request_pipeline::{RequestPipeline, IntoPipeline}}, utils::data_container::DataContainer};
+
+use super::{constraint::{split_placeholder, Constraint, Predicate}, error::RouteError, tree::{RouteMatch, RouteTree}, Method, Methods};
+
+/// A router is a collection of routes that can be used to match a path.
+/// Cloning it is cheap, the pipelines of the routes are shared.
+#[derive(Default, Clone)]
+pub struct Router {
+    /// The routes that are registered with this router.
+    pub(crate) routes: HashMap<String, PathResolver>,
+    /// Routes starting with a placeholder, by its name. They are used when no other route matches.
+    pub(crate) placeholders: Vec<Placeholder>,
+    /// Route capturing the rest of the path, by the name of the parameter. It is used when no other route matches.
+    pub(crate) catch_all: Option<(String, PathResolver)>,
+    /// Pipeline answering the paths below the router that no route matches.
+    pub(crate) not_found: Option<Arc<RequestPipeline>>,
+    /// Middleware running before the pipelines of all routes in this router and its sub-routers.
+    pub(crate) middlewares: Vec<SharedMiddlewareHandler>,
+    /// Request limits of all routes in this router and its sub-routers, unless they set their own.
+    pub(crate) limits: Option<RequestLimits>,
+    /// Predicates that placeholders of the routes can be constrained with.
+    constraints: HashMap<String, Predicate>,
+    /// Conflicts found while registering routes, see `validate`.
+    errors: Vec<RouteError>,
+
+    /// Data that is shared between all routes in this router.
+    pub(crate) data: DataContainer,
+
+    /// Routes compiled for matching, cleared whenever the router changes.
+    compiled: OnceLock<RouteTree>,
+}
+
+impl Router {
+    /// Creates a new router.
+    pub fn new() -> Self {
+        Self {
+            routes: HashMap::new(),
+            placeholders: Vec::new(),
+            catch_all: None,
+            not_found: None,
+            middlewares: Vec::new(),
+            limits: None,
+            constraints: HashMap::new(),
+            errors: Vec::new(),
+            data: DataContainer::default(),
+            compiled: OnceLock::new(),
+        }
+    }
+
+    /// Adds a new route to the router. This is a convenience method for adding a route to the router.
+    /// The controller handles a method, several of them like `[Method::GET, Method::POST]` or `Methods::Any`.
+    /// A route conflicting with the registered ones panics in debug builds, release builds keep
+    /// the new one and report the conflict in `validate`.
+    pub fn add_route<T>(&mut self, path: &str, methods: impl Into<Methods>, controller: impl IntoPipeline<T>) -> &mut Self {
+        let errors = self.add_pipeline(path, methods.into(), controller.into_pipeline(), true);
+        self.check(errors)
+    }
+
+    /// Adds a new route to the router, unless it conflicts with the registered ones.
+    pub fn try_add_route<T>(&mut self, path: &str, methods: impl Into<Methods>, controller: impl IntoPipeline<T>) -> Result<&mut Self, RouteError> {
+        match self.add_pipeline(path, methods.into(), controller.into_pipeline(), false).into_iter().next() {
+            Some(error) => Err(error),
+            None => Ok(self),
+        }
+    }
+
+    /// Adds new module to the router. This is a convenience method for adding a module to the router.
+    /// Conflicts are handled like in `add_route`, routes of the module lose to the registered ones.
+    pub fn add_module(&mut self, path: &str, module: impl Module) -> &mut Self {
+        let mut router = Router::new();
+
+        module.build(&mut router);
+
+        let errors = self.register(path, PathResolver::Router(Box::new(router)), true);
+        self.check(errors)
+    }
+
+    /// Adds new module to the router, unless any of its routes conflicts with the registered ones.
+    pub fn try_add_module(&mut self, path: &str, module: impl Module) -> Result<&mut Self, RouteError> {
+        let mut router = Router::new();
+
+        module.build(&mut router);
+
+        match self.register(path, PathResolver::Router(Box::new(router)), false).into_iter().next() {
+            Some(error) => Err(error),
+            None => Ok(self),
+        }
+    }
+
+    /// Mounts the controller on the prefix, it handles the prefix and every path below it.
+    /// The rest of the path is available as the `path` parameter.
+    pub fn mount<T>(&mut self, prefix: &str, methods: impl Into<Methods>, controller: impl IntoPipeline<T>) -> &mut Self {
+        self.add_route(&format!("{}/*path", prefix.trim_end_matches('/')), methods, controller)
+    }
+
+    /// Mounts the module on the prefix. Paths below it that none of the routes of the module
+    /// match are handled by its root route, with the rest of the path as the `path` parameter.
+    pub fn mount_module(&mut self, prefix: &str, module: impl Module) -> &mut Self {
+        let mut router = Router::new();
+
+        module.build(&mut router);
+
+        if router.catch_all.is_none() {
+            router.catch_all = router.route(&[]).map(|root| ("path".to_string(), root.clone()));
+        }
+
+        let errors = self.register(prefix, PathResolver::Router(Box::new(router)), true);
+        self.check(errors)
+    }
+
+    /// Adds data to the scoped data container.
+    pub fn add_data<T: Send + Sync + 'static>(&mut self, value: T) -> &mut Self {
+        self.compiled.take();
+        self.data.add(value);
+        self
+    }
+
+    /// Adds middleware to all routes in this router and its sub-routers, including the `not_found` controller.
+    /// It runs before the middleware of the routers inside this one and of the routes themselves.
+    pub fn add_middleware<I, M: MiddlewareHandler + Send + Sync + 'static>(&mut self, middleware: impl IntoMiddleware<I, Middleware = M>) -> &mut Self {
+        self.compiled.take();
+        self.middlewares.push(Arc::new(middleware.into_middleware()));
+        self
+    }
+
+    /// Sets the controller answering requests to paths below the router that no route matches.
+    /// The one of the innermost router on the way to the path is used, with the data of the routers like a route.
+    /// It handles any method, a response with the status 200 OK is sent as 404 Not Found.
+    pub fn not_found<T>(&mut self, controller: impl IntoPipeline<T>) -> &mut Self {
+        self.compiled.take();
+        self.not_found = Some(Arc::new(controller.into_pipeline()));
+        self
+    }
+
+    /// Adds a constraint that placeholders of routes added afterwards can use by its name, like `:id<even>`.
+    /// Modules have their own constraints, they don't see the ones of the router they are added to.
+    pub fn add_constraint(&mut self, name: &str, predicate: impl Fn(&str) -> bool + Send + Sync + 'static) -> &mut Self {
+        self.constraints.insert(name.to_string(), Arc::new(predicate));
+        self
+    }
+
+    /// Overrides the request limits for all routes in this router and its sub-routers.
+    pub fn set_limits(&mut self, limits: RequestLimits) -> &mut Self {
+        self.compiled.take();
+        self.limits = Some(limits);
+        self
+    }
+
+    /// Returns the conflicts found while registering the routes of this router and its modules.
+    /// Debug builds panic on them right away, release builds can be checked with this, e.g. in CI.
+    pub fn validate(&self) -> Result<(), Vec<RouteError>> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(self.errors.clone())
+        }
+    }
+
+    fn check(&mut self, errors: Vec<RouteError>) -> &mut Self {
+        if let Some(error) = errors.first() {
+            if cfg!(debug_assertions) {
+                panic!("{error}");
+            }
+        }
+        self
+    }
+
+    pub(crate) fn add_pipeline(&mut self, path: &str, methods: Methods, pipeline: RequestPipeline, replace: bool) -> Vec<RouteError> {
+        self.register(path, PathResolver::Pipeline(Pipelines::new(methods, pipeline)), replace)
+    }
+
+    /// Inserts a new route into the router creating sub-routers as needed.
+    /// :id can be used like a placeholder to match any path segment, :id<u64> only matches segments
+    /// satisfying the constraint. *rest as the last segment matches the rest of the path.
+    /// Conflicts with the registered routes are recorded for `validate`.
+    #[cfg(test)]
+    pub(crate) fn insert(&mut self, path: &str, resolver: PathResolver) {
+        self.register(path, resolver, true);
+    }
+
+    /// Inserts the route and returns its conflicts with the registered ones.
+    /// Nothing is inserted if there are any, unless `replace` is set.
+    fn register(&mut self, path: &str, mut resolver: PathResolver, replace: bool) -> Vec<RouteError> {
+        let segments = path.split('/').filter(|s| !s.is_empty()).collect::<Vec<_>>();
+
+        // Nothing can follow the rest of the path.
+        let catch_all = segments.iter().position(|segment| segment.starts_with('*'));
+        if catch_all.is_some_and(|position| position + 1 < segments.len() || segments[position].len() == 1) {
+            let error = RouteError::InvalidPath { path: format!("/{}", segments.join("/")), reason: "a catch-all needs a name and has to be the last segment" };
+            self.errors.push(error.clone());
+            return vec![error];
+        }
+
+        let constraints = segments
+            .iter()
+            .map(|segment| match segment.strip_prefix(':').map(split_placeholder) {
+                Some((_, Some(source))) => Constraint::parse(source, &self.constraints).map(Some),
+                _ => Ok(None),
+            })
+            .collect::<Result<Vec<_>, _>>();
+        let constraints = match constraints {
+            Ok(constraints) => constraints,
+            Err(reason) => {
+                let error = RouteError::InvalidPath { path: format!("/{}", segments.join("/")), reason };
+                self.errors.push(error.clone());
+                return vec![error];
+            }
+        };
+
+        let errors = self.conflicts(&segments, &resolver);
+        if !errors.is_empty() && !replace {
+            return errors;
+        }
+
+        // Conflicts within a module are kept with the full path.
+        if let PathResolver::Router(router) = &mut resolver {
+            let prefix = format!("/{}", segments.join("/"));
+            let inner = std::mem::take(&mut router.errors);
+            self.errors.extend(inner.into_iter().map(|error| error.prefixed(&prefix)));
+        }
+
+        self.compiled.take();
+        self.insert_segments(&segments, &constraints, resolver);
+        self.errors.extend(errors.iter().cloned());
+        errors
+    }
+
+    /// Finds the registered routes the resolver at the path would collide with.
+    fn conflicts(&self, segments: &[&str], resolver: &PathResolver) -> Vec<RouteError> {
+        // A router brings its own routes, each of them has to fit.
+        let mounted = matches!(resolver, PathResolver::Router(_));
+        let routes = match resolver {
+            PathResolver::Router(router) => router.definitions(),
+            resolver => vec![(String::new(), resolver)],
+        };
+
+        let mut errors = Vec::new();
+        for (pattern, resolver) in routes {
+            let mut segments = segments.to_vec();
+            segments.extend(pattern.split('/').filter(|s| !s.is_empty()));
+            let path = format!("/{}", segments.join("/"));
+
+            let mut existing = Vec::new();
+            self.matching(&segments, "", &mut existing);
+
+            for (existing_path, existing) in existing {
+                if existing_path != path {
+                    errors.push(RouteError::AmbiguousPlaceholder { path: path.clone(), existing: existing_path });
+                    continue;
+                }
+
+                let duplicates = match (existing, resolver) {
+                    (PathResolver::Pipeline(existing), PathResolver::Pipeline(pipelines)) => Some(existing.duplicates(pipelines)),
+                    _ => None,
+                };
+
+                match duplicates {
+                    Some(methods) if !methods.is_empty() => {
+                        errors.extend(methods.into_iter().map(|method| RouteError::DuplicateRoute { method, path: path.clone() }));
+                    }
+                    // The methods are added to the existing route.
+                    Some(_) if !mounted => {}
+                    // Routes of a module are mounted next to the registered ones, which take precedence.
+                    _ if mounted => errors.push(RouteError::UnreachableRoute { route: describe(&path, resolver), by: describe(&path, existing) }),
+                    _ => errors.push(RouteError::UnreachableRoute { route: describe(&path, existing), by: describe(&path, resolver) }),
+                }
+            }
+        }
+        errors
+    }
+
+    /// Collects the registered routes of the same shape as the segments,
+    /// with placeholders of any name but the same constraint standing in for each other.
+    fn matching<'a>(&'a self, segments: &[&str], prefix: &str, found: &mut Vec<(String, &'a PathResolver)>) {
+        fn below<'a>(resolver: &'a PathResolver, rest: &[&str], prefix: String, found: &mut Vec<(String, &'a PathResolver)>) {
+            match resolver {
+                PathResolver::Router(router) => router.matching(rest, &prefix, found),
+                resolver if rest.is_empty() => found.push((if prefix.is_empty() { "/".to_string() } else { prefix }, resolver)),
+                _ => {}
+            }
+        }
+
+        let Some((segment, rest)) = segments.split_first() else {
+            if let Some(resolver) = self.routes.get("") {
+                below(resolver, &[], prefix.to_string(), found);
+            }
+            return;
+        };
+
+        if let Some(placeholder) = segment.strip_prefix(':') {
+            let (_, constraint) = split_placeholder(placeholder);
+            for placeholder in self.placeholders.iter().filter(|placeholder| placeholder.constraint() == constraint) {
+                below(&placeholder.resolver, rest, format!("{prefix}/{placeholder}"), found);
+            }
+        } else if segment.starts_with('*') {
+            if let Some((name, resolver)) = &self.catch_all {
+                below(resolver, rest, format!("{prefix}/*{name}"), found);
+            }
+        } else if let Some(resolver) = self.routes.get(*segment) {
+            below(resolver, rest, format!("{prefix}/{segment}"), found);
+        }
+
+        // Routes of a module mounted at this path.
+        if let Some(PathResolver::Router(router)) = self.routes.get("") {
+            router.matching(segments, prefix, found);
+        }
+    }
+
+    /// Returns the paths and resolvers of all routes, relative to this router.
+    fn definitions(&self) -> Vec<(String, &PathResolver)> {
+        fn below<'a>(resolver: &'a PathResolver, prefix: &str, definitions: &mut Vec<(String, &'a PathResolver)>) {
+            match resolver {
+                PathResolver::Router(router) => definitions.extend(router.definitions().into_iter().map(|(path, resolver)| (format!("{prefix}{path}"), resolver))),
+                resolver => definitions.push((prefix.to_string(), resolver)),
+            }
+        }
+
+        let mut definitions = Vec::new();
+        for (segment, resolver) in &self.routes {
+            let prefix = if segment.is_empty() { String::new() } else { format!("/{segment}") };
+            below(resolver, &prefix, &mut definitions);
+        }
+        for placeholder in &self.placeholders {
+            below(&placeholder.resolver, &format!("/{placeholder}"), &mut definitions);
+        }
+        if let Some((name, resolver)) = &self.catch_all {
+            below(resolver, &format!("/*{name}"), &mut definitions);
+        }
+        definitions
+    }
+
+    fn insert_segments(&mut self, segments: &[&str], constraints: &[Option<Constraint>], resolver: PathResolver) {
+        // The route of the router itself.
+        let Some((segment, rest)) = segments.split_first() else {
+            match self.routes.get_mut("") {
+                Some(existing) => place(existing, resolver),
+                None => { self.routes.insert("".to_string(), resolver); }
+            }
+            return;
+        };
+
+        // A route registered by a module mounted here is extended there.
+        let in_module = matches!(self.routes.get(""), Some(PathResolver::Router(router)) if router.route(segments).is_some())
+            && self.child(segment).and_then(|child| route_below(child, rest)).is_none();
+        if in_module {
+            if let Some(PathResolver::Router(router)) = self.routes.get_mut("") {
+                router.insert_segments(segments, constraints, resolver);
+            }
+            return;
+        }
+
+        let (constraint, constraints) = constraints.split_first().expect("a constraint for every segment");
+        match self.child_mut(segment) {
+            // Sub-router, the route goes there.
+            Some(PathResolver::Router(router)) => router.insert_segments(rest, constraints, resolver),
+            Some(child) if rest.is_empty() => place(child, resolver),
+            // Create a new router and insert the existing resolver as an "" route.
+            Some(child) => {
+                let mut router = Router::new();
+                router.insert_segments(rest, constraints, resolver);
+                let existing = std::mem::replace(child, PathResolver::Router(Box::new(router)));
+                if let PathResolver::Router(router) = child {
+                    router.routes.insert("".to_string(), existing);
+                }
+            }
+            None if rest.is_empty() => self.add_child(segment, constraint.clone(), resolver),
+            None => {
+                let mut router = Router::new();
+                router.insert_segments(rest, constraints, resolver);
+                self.add_child(segment, constraint.clone(), PathResolver::Router(Box::new(router)));
+            }
+        }
+    }
+
+    /// Returns the resolver registered for the segment, a placeholder is found by its name and constraint,
+    /// a catch-all by its name.
+    fn child(&self, segment: &str) -> Option<&PathResolver> {
+        if let Some(name) = segment.strip_prefix('*') {
+            return self.catch_all.as_ref().filter(|(n, _)| n == name).map(|(_, resolver)| resolver);
+        }
+
+        match segment.strip_prefix(':') {
+            Some(placeholder) => self.placeholders.iter().find(|p| p.is(placeholder)).map(|p| &p.resolver),
+            None => self.routes.get(segment),
+        }
+    }
+
+    fn child_mut(&mut self, segment: &str) -> Option<&mut PathResolver> {
+        if let Some(name) = segment.strip_prefix('*') {
+            return self.catch_all.as_mut().filter(|(n, _)| n == name).map(|(_, resolver)| resolver);
+        }
+
+        match segment.strip_prefix(':') {
+            Some(placeholder) => self.placeholders.iter_mut().find(|p| p.is(placeholder)).map(|p| &mut p.resolver),
+            None => self.routes.get_mut(segment),
+        }
+    }
+
+    fn add_child(&mut self, segment: &str, constraint: Option<Constraint>, resolver: PathResolver) {
+        if let Some(name) = segment.strip_prefix('*') {
+            self.catch_all = Some((name.to_string(), resolver));
+            return;
+        }
+
+        match segment.strip_prefix(':') {
+            Some(placeholder) => self.placeholders.push(Placeholder {
+                name: split_placeholder(placeholder).0.to_string(),
+                constraint,
+                resolver,
+            }),
+            None => { self.routes.insert(segment.to_string(), resolver); }
+        }
+    }
+
+    /// Returns the resolver registered for exactly this path, placeholders included.
+    fn route(&self, segments: &[&str]) -> Option<&PathResolver> {
+        let Some((segment, rest)) = segments.split_first() else {
+            return route_below(self.routes.get("")?, &[]);
+        };
+
+        self.child(segment)
+            .and_then(|child| route_below(child, rest))
+            .or_else(|| match self.routes.get("") {
+                Some(PathResolver::Router(router)) => router.route(segments),
+                _ => None,
+            })
+    }
+
+    /// Compiles the routes for matching. This happens on the first match otherwise,
+    /// the server does it when it starts and when the router is replaced so requests don't have to.
+    pub fn compile(&self) {
+        self.routes();
+    }
+
+    fn routes(&self) -> &RouteTree {
+        self.compiled.get_or_init(|| RouteTree::compile(self))
+    }
+
+    /// Finds the route matching the path without allocating.
+    /// Static segments are preferred over placeholders, which are tried when the static ones lead nowhere,
+    /// and a catch-all is tried last. It also matches when nothing of the path is left.
+    pub fn find<'r, 'p>(&'r self, path: &'p str) -> Option<RouteMatch<'r, 'p>> {
+        self.routes().find(path)
+    }
+
+    /// Finds a route that matches the given path and returns the resolver.
+    /// :id can be used like a placeholder to match any path segment.
+    pub fn resolve(&self, path: &str) -> Option<(&PathResolver, DataContainer)> {
+        self.find(path).map(|found| (found.resolver(), found.into_data()))
+    }
+}
+
+// Debug impl
+impl std::fmt::Debug for Router {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Router")
+            .field("routes", &self.routes)
+            .field("placeholders", &self.placeholders)
+            .field("catch_all", &self.catch_all)
+            .finish()
+    }
+}
+
+fn route_below<'a>(resolver: &'a PathResolver, rest: &[&str]) -> Option<&'a PathResolver> {
+    match resolver {
+        PathResolver::Router(router) => router.route(rest),
+        resolver => rest.is_empty().then_some(resolver),
+    }
+}
+
+/// Puts the resolver where another one is registered for the same path.
+/// Methods are added to an existing route, a module gets the existing route as its own.
+fn place(existing: &mut PathResolver, resolver: PathResolver) {
+    match (existing, resolver) {
+        (PathResolver::Router(router), resolver) => router.insert_segments(&[], &[], resolver),
+        (PathResolver::Pipeline(pipelines), PathResolver::Pipeline(new)) => pipelines.extend(new),
+        (existing, PathResolver::Router(mut router)) => {
+            let route = std::mem::replace(existing, PathResolver::Placeholder(String::new()));
+            router.insert_segments(&[], &[], route);
+            *existing = PathResolver::Router(router);
+        }
+        (existing, resolver) => *existing = resolver,
+    }
+}
+
+/// Responses of a `not_found` controller are sent as 404 unless it chose another status than 200 OK.
+pub(crate) fn not_found(response: Response) -> Response {
+    match response.status {
+        ResponseStatus::Ok => response.with_status(ResponseStatus::NotFound),
+        _ => response,
+    }
+}
+
+/// Describes a route for error messages, with the methods it handles.
+fn describe(path: &str, resolver: &PathResolver) -> String {
+    match resolver {
+        PathResolver::Pipeline(pipelines) => format!("{} {path}", pipelines.names().join(", ")),
+        _ => path.to_string(),
+    }
+}
+
+/// Route starting with a placeholder.
+#[derive(Debug, Clone)]
+pub(crate) struct Placeholder {
+    pub(crate) name: String,
+    pub(crate) constraint: Option<Constraint>,
+    pub(crate) resolver: PathResolver,
+}
+
+impl Placeholder {
+    fn constraint(&self) -> Option<&str> {
+        self.constraint.as_ref().map(Constraint::source)
+    }
+
+    /// Returns whether this is the placeholder written like `id<u64>`.
+    fn is(&self, placeholder: &str) -> bool {
+        let (name, constraint) = split_placeholder(placeholder);
+        self.name == name && self.constraint() == constraint
+    }
+}
+
+impl std::fmt::Display for Placeholder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.constraint() {
+            Some(constraint) => write!(f, ":{}<{constraint}>", self.name),
+            None => write!(f, ":{}", self.name),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Enum that represents all possible ways to resolve a path.
+pub enum PathResolver {
+    Router(Box<Router>),
+    Placeholder(String),
+    Pipeline(Pipelines),
+    /// Controller of a router answering the paths that none of its routes match.
+    NotFound(Arc<RequestPipeline>),
+}
+
+/// Pipelines of a route, by the method they handle.
+#[derive(Debug, Clone, Default)]
+pub struct Pipelines {
+    pub(crate) methods: HashMap<Method, Arc<RequestPipeline>>,
+    /// Pipeline handling the methods that have no pipeline of their own.
+    pub(crate) any: Option<Arc<RequestPipeline>>,
+}
+
+impl Pipelines {
+    fn new(methods: Methods, pipeline: RequestPipeline) -> Self {
+        let pipeline = Arc::new(pipeline);
+        match methods {
+            Methods::Only(methods) => Self {
+                methods: methods.into_iter().map(|method| (method, pipeline.clone())).collect(),
+                any: None,
+            },
+            Methods::Any => Self { methods: HashMap::new(), any: Some(pipeline) },
+        }
+    }
+
+    /// Returns the pipeline handling the method.
+    /// HEAD requests are handled by the GET pipeline unless they have their own.
+    pub fn get(&self, method: &Method) -> Option<&Arc<RequestPipeline>> {
+        self.methods
+            .get(method)
+            .or_else(|| if *method == Method::HEAD { self.methods.get(&Method::GET) } else { None })
+            .or(self.any.as_ref())
+    }
+
+    /// Returns whether the method has a pipeline of its own.
+    pub fn contains(&self, method: &Method) -> bool {
+        self.methods.contains_key(method)
+    }
+
+    /// Returns the sorted names of the methods, `*` stands for any method.
+    fn names(&self) -> Vec<&str> {
+        let mut names = self.methods.keys().map(Method::as_str).collect::<Vec<_>>();
+        names.sort_unstable();
+        names.extend(self.any.as_ref().map(|_| "*"));
+        names
+    }
+
+    /// Returns the names of the methods both of the routes handle.
+    fn duplicates(&self, other: &Pipelines) -> Vec<String> {
+        let mut methods = other.methods.keys().filter(|method| self.contains(method)).map(Method::to_string).collect::<Vec<_>>();
+        methods.sort_unstable();
+        if self.any.is_some() && other.any.is_some() {
+            methods.push("*".to_string());
+        }
+        methods
+    }
+
+    fn extend(&mut self, other: Pipelines) {
+        self.methods.extend(other.methods);
+        if other.any.is_some() {
+            self.any = other.any;
+        }
+    }
+}
+
+impl PartialEq for PathResolver {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (PathResolver::Placeholder(a), PathResolver::Placeholder(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl PathResolver {
+    /// Returns the request limits of the route handling the method, if it overrides them.
+    pub fn limits(&self, method: &Method) -> Option<RequestLimits> {
+        self.pipeline(method).and_then(|pipeline| pipeline.limits.clone())
+    }
+
+    /// Returns the pipeline handling the method, if this is a route.
+    pub(crate) fn pipeline(&self, method: &Method) -> Option<&Arc<RequestPipeline>> {
+        match self {
+            PathResolver::Pipeline(pipelines) => pipelines.get(method),
+            PathResolver::NotFound(pipeline) => Some(pipeline),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of the `Allow` header of the route, its methods together with
+    /// the ones answered automatically.
+    pub fn allowed_methods(&self) -> String {
+        let mut methods: Vec<&Method> = match self {
+            PathResolver::Pipeline(pipelines) => pipelines.methods.keys().collect(),
+            _ => Vec::new(),
+        };
+        if methods.contains(&&Method::GET) && !methods.contains(&&Method::HEAD) {
+            methods.push(&Method::HEAD);
+        }
+        if !methods.contains(&&Method::OPTIONS) {
+            methods.push(&Method::OPTIONS);
+        }
+        let mut methods = methods.into_iter().map(Method::as_str).collect::<Vec<_>>();
+        methods.sort_unstable();
+        methods.join(", ")
+    }
+
+    /// Returns new response based on the request
+    pub fn resolve(&self, request: &Request, data: DataContainer) -> Response {
+        match self {
+            PathResolver::Placeholder(data) => Response::new().with_status(ResponseStatus::Ok).with_body(data.clone().into_bytes()),
+            PathResolver::Pipeline(_) => {
+                // Get the pipeline, methods without one are answered with the allowed methods
+                let pipeline = match self.pipeline(&request.method) {
+                    Some(pipeline) => pipeline,
+                    None => {
+                        let status = match request.method {
+                            Method::OPTIONS => ResponseStatus::Ok,
+                            _ => ResponseStatus::MethodNotAllowed,
+                        };
+                        let mut response = Response::new().with_status(status);
+                        response.headers.insert("Allow".to_string(), self.allowed_methods());
+                        return response;
+                    }
+                };
+
+                // Resolve the pipeline, other requests of the route may run it at the same time
+                pipeline.handle(request.clone(), data)
+            }
+            PathResolver::NotFound(pipeline) => not_found(pipeline.handle(request.clone(), data)),
+            _ => Response::new().with_status(ResponseStatus::InternalServerError),
+        }
+    }
+}
+
+/// Router shared by the server and its connections.
+///
+/// Every request is served by a snapshot of the router taken when it starts, so changing
+/// or replacing the router never waits for in-flight requests. They finish with the routes
+/// they started with, while new requests see the change right away.
+#[derive(Debug, Default)]
+pub struct SharedRouter {
+    current: RwLock<Arc<Router>>,
+}
+
+impl SharedRouter {
+    /// Creates a shared router starting with the router.
+    pub fn new(router: Router) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(router)),
+        }
+    }
+
+    /// Returns the current snapshot of the router.
+    pub fn load(&self) -> Arc<Router> {
+        self.current.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Replaces the router.
+    pub fn store(&self, router: Router) {
+        router.compile();
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(router);
+    }
+
+    /// Changes the router. When requests are still using the current snapshot,
+    /// the change is made on a copy which then replaces it.
+    /// The routes are compiled by the next match, or by `compile` when that is too late.
+    pub fn update<R>(&self, f: impl FnOnce(&mut Router) -> R) -> R {
+        let mut current = self.current.write().unwrap_or_else(|e| e.into_inner());
+        f(Arc::make_mut(&mut current))
+    }
+
+    /// Compiles the routes of the current router, so the first request doesn't have to.
+    pub fn compile(&self) {
+        self.load().compile();
+    }
+}
+
+pub trait Module {
+    fn build(self, router: &mut Router);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::router::PathParams;
+
+    #[test]
+    fn test_router() {
+        let mut router = Router::new();
+
+        router.insert("/", PathResolver::Placeholder("Root".to_string()));
+        router.insert("/hello/world", PathResolver::Placeholder("Hello World".to_string()));
+        router.insert("/hello/world/test", PathResolver::Placeholder("Hello World test".to_string()));
+
+        router.insert("/hello/:name", PathResolver::Placeholder("Hello Name".to_string()));
+        router.insert("/hello/:name/:age", PathResolver::Placeholder("Hello Name Age".to_string()));
+
+        assert_eq!(router.resolve("/").unwrap().0, &PathResolver::Placeholder("Root".to_string()));
+        assert_eq!(router.resolve("/hello/world").unwrap().0, &PathResolver::Placeholder("Hello World".to_string()));
+        assert_eq!(router.resolve("/hello/world/test").unwrap().0, &PathResolver::Placeholder("Hello World test".to_string()));
+        assert_eq!(router.resolve("/hello/John").unwrap().0, &PathResolver::Placeholder("Hello Name".to_string()));
+        assert_eq!(router.resolve("/hello/John/20").unwrap().0, &PathResolver::Placeholder("Hello Name Age".to_string()));
+    }
+
+    #[test]
+    fn test_find() {
+        let mut router = Router::new();
+
+        router.insert("/hello/world/test", PathResolver::Placeholder("Hello World test".to_string()));
+        router.insert("/hello/:name/:age", PathResolver::Placeholder("Hello Name Age".to_string()));
+
+        // A static segment leading nowhere falls back to the placeholder
+        let found = router.find("/hello/world/20").unwrap();
+        assert_eq!(found.resolver(), &PathResolver::Placeholder("Hello Name Age".to_string()));
+        assert_eq!(found.params().collect::<Vec<_>>(), vec![("name", "world"), ("age", "20")]);
+
+        // Empty segments are ignored
+        assert!(router.find("//hello//world/test/").is_some());
+        assert!(router.find("/hello").is_none());
+        assert!(router.find("/hello/world/test/more").is_none());
+
+        // Changes are picked up by the next match
+        router.insert("/hello", PathResolver::Placeholder("Hello".to_string()));
+        assert_eq!(router.find("/hello").unwrap().resolver(), &PathResolver::Placeholder("Hello".to_string()));
+    }
+
+    #[test]
+    fn test_placeholder_names() {
+        let mut router = Router::new();
+
+        router.insert("/users/:id", PathResolver::Placeholder("User".to_string()));
+        router.insert("/users/:user_id/posts", PathResolver::Placeholder("Posts".to_string()));
+        router.insert("/users/:user_id/posts/:post_id", PathResolver::Placeholder("Post".to_string()));
+
+        let found = router.find("/users/5").unwrap();
+        assert_eq!(found.params().collect::<Vec<_>>(), vec![("id", "5")]);
+
+        let found = router.find("/users/5/posts/7").unwrap();
+        assert_eq!(found.resolver(), &PathResolver::Placeholder("Post".to_string()));
+        assert_eq!(found.params().collect::<Vec<_>>(), vec![("user_id", "5"), ("post_id", "7")]);
+
+        // Registering the same route with the same names is not a conflict
+        router.insert("/users/:id", PathResolver::Placeholder("User again".to_string()));
+        assert_eq!(router.find("/users/5").unwrap().resolver(), &PathResolver::Placeholder("User again".to_string()));
+    }
+
+    #[test]
+    fn test_placeholder_conflict() {
+        let mut router = Router::new();
+
+        router.insert("/users/:id", PathResolver::Placeholder("User".to_string()));
+        router.insert("/users/:user_id", PathResolver::Placeholder("Other user".to_string()));
+
+        assert_eq!(router.validate(), Err(vec![RouteError::AmbiguousPlaceholder {
+            path: "/users/:user_id".to_string(),
+            existing: "/users/:id".to_string(),
+        }]));
+    }
+
+    #[test]
+    fn test_route_conflicts() {
+        let mut router = Router::new();
+        router.try_add_route("/users/:id", Method::GET, || "User").unwrap();
+        router.try_add_route("/users/:id/", Method::POST, || "Updated").unwrap();
+
+        // The existing route is kept
+        assert_eq!(router.try_add_route("users/:id", Method::GET, || "Other").unwrap_err(), RouteError::DuplicateRoute {
+            method: "GET".to_string(),
+            path: "/users/:id".to_string(),
+        });
+        assert!(router.try_add_route("/users/:user_id", Method::PUT, || "Other").is_err());
+        assert!(router.validate().is_ok());
+
+        let (resolver, _) = router.resolve("/users/5").unwrap();
+        assert!(resolver.limits(&Method::GET).is_none());
+        assert!(matches!(resolver, PathResolver::Pipeline(pipelines) if pipelines.methods.len() == 2));
+    }
+
+    #[test]
+    fn test_methods() {
+        let purge: Method = "PURGE".parse().unwrap();
+
+        let mut router = Router::new();
+        router.add_route("/users", [Method::GET, Method::POST], || "Users");
+        router.add_route("/users", purge.clone(), || "Purged");
+        router.add_route("/files/*path", Methods::Any, || "File");
+        router.add_route("/files/*path", Method::DELETE, || "Deleted");
+
+        let PathResolver::Pipeline(users) = router.find("/users").unwrap().resolver() else { panic!() };
+        assert!(users.contains(&Method::GET) && users.contains(&Method::POST) && users.contains(&purge));
+        assert!(Arc::ptr_eq(users.get(&Method::GET).unwrap(), users.get(&Method::POST).unwrap()));
+        assert!(users.get(&Method::PUT).is_none());
+
+        // Methods with their own pipeline take precedence over any method.
+        let PathResolver::Pipeline(files) = router.find("/files/a").unwrap().resolver() else { panic!() };
+        assert!(!Arc::ptr_eq(files.get(&Method::DELETE).unwrap(), files.get(&"MKCOL".parse().unwrap()).unwrap()));
+
+        assert_eq!(router.try_add_route("/files/*path", Methods::Any, || "Other").unwrap_err(), RouteError::DuplicateRoute {
+            method: "*".to_string(),
+            path: "/files/*path".to_string(),
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "Route GET /users is registered twice")]
+    fn test_duplicate_panics() {
+        let mut router = Router::new();
+        router.add_route("/users", Method::GET, || "Users");
+        router.add_route("/users", Method::GET, || "Users");
+    }
+
+    #[test]
+    fn test_module_conflicts() {
+        struct Users;
+
+        impl Module for Users {
+            fn build(self, router: &mut Router) {
+                router
+                    .add_route("/", Method::GET, || "Users")
+                    .add_route("/list", Method::POST, || "Added")
+                    .add_route("/:user_id/posts", Method::GET, || "Posts");
+            }
+        }
+
+        let mut router = Router::new();
+        router
+            .add_route("/users/list", Method::GET, || "List")
+            .add_route("/users/:id/posts", Method::GET, || "Posts");
+
+        let error = router.try_add_module("/users", Users).unwrap_err();
+        assert_eq!(error.to_string(), "Route POST /users/list is unreachable, GET /users/list takes its place");
+
+        // Recorded for validate, the module can't take the place of registered routes
+        router.insert("/users", PathResolver::Router(Box::new({
+            let mut module = Router::new();
+            Users.build(&mut module);
+            module
+        })));
+        let mut errors = router.validate().unwrap_err();
+        errors.sort_by_key(|error| error.to_string());
+        assert_eq!(errors, vec![
+            RouteError::AmbiguousPlaceholder { path: "/users/:user_id/posts".to_string(), existing: "/users/:id/posts".to_string() },
+            RouteError::UnreachableRoute { route: "POST /users/list".to_string(), by: "GET /users/list".to_string() },
+        ]);
+
+        assert!(router.find("/users").is_some());
+        assert!(matches!(router.find("/users/list").unwrap().resolver(), PathResolver::Pipeline(pipelines) if !pipelines.contains(&Method::POST)));
+        assert_eq!(router.find("/users/5/posts").unwrap().params().collect::<Vec<_>>(), vec![("id", "5")]);
+    }
+
+    #[test]
+    fn test_catch_all() {
+        let mut router = Router::new();
+
+        router.insert("/files/*path", PathResolver::Placeholder("File".to_string()));
+        router.insert("/files/:id/meta", PathResolver::Placeholder("Meta".to_string()));
+        router.insert("/*page", PathResolver::Placeholder("App".to_string()));
+
+        let found = router.find("/files/docs//readme.md/").unwrap();
+        assert_eq!(found.resolver(), &PathResolver::Placeholder("File".to_string()));
+        assert_eq!(found.params().collect::<Vec<_>>(), vec![("path", "docs//readme.md")]);
+
+        // Other routes are preferred, the catch-all matches when they lead nowhere
+        assert_eq!(router.find("/files/5/meta").unwrap().resolver(), &PathResolver::Placeholder("Meta".to_string()));
+        assert_eq!(router.find("/files/5/meta/more").unwrap().resolver(), &PathResolver::Placeholder("File".to_string()));
+        assert_eq!(router.find("/files").unwrap().params().collect::<Vec<_>>(), vec![("path", "")]);
+
+        let found = router.find("/settings/profile").unwrap();
+        assert_eq!(found.resolver(), &PathResolver::Placeholder("App".to_string()));
+        assert_eq!(found.params().collect::<Vec<_>>(), vec![("page", "settings/profile")]);
+        assert!(router.find("/").is_some());
+
+        // Values are decoded for the controllers, escaped slashes in the rest stay escaped
+        let (_, data) = router.resolve("/files/a%20b/c").unwrap();
+        assert_eq!(data.get::<PathParams>().unwrap().get_param("path"), Some("a b/c".to_string()));
+        let (_, data) = router.resolve("/files/..%2F..%2Fetc%2Fpasswd").unwrap();
+        assert_eq!(data.get::<PathParams>().unwrap().get_param("path"), Some("..%2F..%2Fetc%2Fpasswd".to_string()));
+
+        router.insert("/broken/*path/more", PathResolver::Placeholder("Broken".to_string()));
+        router.insert("/files/*rest", PathResolver::Placeholder("Other".to_string()));
+        assert_eq!(router.validate(), Err(vec![
+            RouteError::InvalidPath { path: "/broken/*path/more".to_string(), reason: "a catch-all needs a name and has to be the last segment" },
+            RouteError::AmbiguousPlaceholder { path: "/files/*rest".to_string(), existing: "/files/*path".to_string() },
+        ]));
+    }
+
+    #[test]
+    fn test_constraints() {
+        let mut router = Router::new();
+
+        router.add_constraint("even", |value| value.parse::<u64>().is_ok_and(|value| value % 2 == 0));
+        router.insert("/users/:name", PathResolver::Placeholder("Name".to_string()));
+        router.insert("/users/:id<u64>", PathResolver::Placeholder("Id".to_string()));
+        router.insert("/users/:id<even>/posts", PathResolver::Placeholder("Even".to_string()));
+
+        let found = router.find("/users/42").unwrap();
+        assert_eq!(found.resolver(), &PathResolver::Placeholder("Id".to_string()));
+        assert_eq!(found.into_data().get::<PathParams>().unwrap().get_parsed::<u64>("id"), Some(42));
+
+        // A segment not satisfying the constraint falls through to other routes
+        assert_eq!(router.find("/users/john").unwrap().resolver(), &PathResolver::Placeholder("Name".to_string()));
+        assert!(router.find("/users/4/posts").is_some());
+        assert!(router.find("/users/5/posts").is_none());
+
+        // Constraints check the decoded segment
+        let found = router.find("/users/%34%32").unwrap();
+        assert_eq!(found.resolver(), &PathResolver::Placeholder("Id".to_string()));
+        assert_eq!(found.into_data().get::<PathParams>().unwrap().get_parsed::<u64>("id"), Some(42));
+
+        // Placeholders with different constraints are different routes
+        router.insert("/users/:user_id<u64>", PathResolver::Placeholder("Other".to_string()));
+        router.insert("/users/:id<uuid>", PathResolver::Placeholder("Unknown".to_string()));
+        assert_eq!(router.validate().unwrap_err().len(), 2);
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_regex_constraints() {
+        let mut router = Router::new();
+
+        router.insert("/posts/:slug<[a-z0-9-]+>", PathResolver::Placeholder("Slug".to_string()));
+
+        let found = router.find("/posts/hello-world").unwrap();
+        assert_eq!(found.params().collect::<Vec<_>>(), vec![("slug", "hello-world")]);
+        assert!(found.into_data().get::<PathParams>().unwrap().get_parsed::<String>("slug").is_none());
+
+        // The whole segment has to match
+        assert!(router.find("/posts/Hello").is_none());
+
+        // The decoded segment is matched, like the value the controller gets
+        router.insert("/names/:name<[a-z ]+>", PathResolver::Placeholder("Name".to_string()));
+        router.insert(r"/files/:name<[a-z]+\W[a-z]+>", PathResolver::Placeholder("File".to_string()));
+        let found = router.find("/names/a%20b").unwrap();
+        assert_eq!(found.into_data().get::<PathParams>().unwrap().get_param("name").unwrap(), "a b");
+        let found = router.find("/files/a%2Fb").unwrap();
+        assert_eq!(found.into_data().get::<PathParams>().unwrap().get_param("name").unwrap(), "a/b");
+        assert!(router.find("/files/a/b").is_none());
+    }
+
+    #[test]
+    fn test_mount() {
+        struct Assets;
+
+        impl Module for Assets {
+            fn build(self, router: &mut Router) {
+                router
+                    .add_route("/", Method::GET, || "Asset")
+                    .add_route("/manifest.json", Method::GET, || "Manifest");
+            }
+        }
+
+        let mut router = Router::new();
+        router
+            .mount("/proxy/", Method::GET, || "Proxy")
+            .mount_module("/assets", Assets);
+
+        let (_, data) = router.resolve("/proxy/api/users").unwrap();
+        assert_eq!(data.get::<PathParams>().unwrap().get_param("path"), Some("api/users".to_string()));
+        assert!(router.resolve("/proxy").is_some());
+
+        assert!(router.find("/assets/manifest.json").unwrap().params().next().is_none());
+        let found = router.find("/assets/css/site.css").unwrap();
+        assert_eq!(found.params().collect::<Vec<_>>(), vec![("path", "css/site.css")]);
+        assert!(matches!(found.resolver(), PathResolver::Pipeline(pipelines) if pipelines.contains(&Method::GET)));
+    }
+
+    #[test]
+    fn test_not_found() {
+        use crate::{pipeline::controller::Data, server::response::Response};
+
+        struct Scope(&'static str);
+        struct Api;
+
+        impl Module for Api {
+            fn build(self, router: &mut Router) {
+                router
+                    .add_data(Scope("api"))
+                    .add_route("/users", Method::GET, || "Users")
+                    .not_found(|scope: Data<'_, Scope>| scope.0);
+            }
+        }
+
+        let mut router = Router::new();
+        router
+            .add_data(Scope("root"))
+            .add_route("/", Method::GET, || "Root")
+            .add_module("/api", Api)
+            .not_found(|| Response::new().with_status(ResponseStatus::Custom("410 Gone".to_string())));
+
+        let respond = |path: &str| {
+            let (resolver, data) = router.resolve(path).unwrap();
+            resolver.resolve(&Request { method: Method::POST, ..Default::default() }, data)
+        };
+
+        let response = respond("/api/users/5");
+        assert!(matches!(response.status, ResponseStatus::NotFound));
+        assert_eq!(response.body, b"\"api\"");
+        assert!(matches!(respond("/api/users").status, ResponseStatus::MethodNotAllowed));
+        assert_eq!(respond("/api").body, b"\"api\"");
+        assert_eq!(respond("/missing").status.code(), 410);
+        assert!(matches!(respond("/").status, ResponseStatus::MethodNotAllowed));
+    }
+
+    #[test]
+    fn test_middleware() {
+        use std::sync::Mutex;
+        use crate::{pipeline::{controller::ConfigurableController, request_pipeline::PipelineData}, server::response::Response};
+
+        static ORDER: Mutex<Vec<&str>> = Mutex::new(Vec::new());
+
+        fn outer(_: &mut PipelineData) -> Option<Response> {
+            ORDER.lock().unwrap().push("outer");
+            None
+        }
+
+        fn auth(pipeline: &mut PipelineData) -> Option<Response> {
+            ORDER.lock().unwrap().push("auth");
+            match pipeline.request.headers.get("Authorization") {
+                Some(_) => None,
+                None => Some(Response::new().with_status(ResponseStatus::Custom("401 Unauthorized".to_string()))),
+            }
+        }
+
+        fn route(_: &mut PipelineData) -> Option<Response> {
+            ORDER.lock().unwrap().push("route");
+            None
+        }
+
+        struct Admin;
+
+        impl Module for Admin {
+            fn build(self, router: &mut Router) {
+                router
+                    .add_middleware(auth)
+                    .add_route("/users", Method::GET, (|| "Users").with_middleware(route));
+            }
+        }
+
+        // Middleware applies to routes added before it too.
+        let mut router = Router::new();
+        router
+            .add_route("/", Method::GET, || "Root")
+            .add_module("/admin", Admin)
+            .add_middleware(outer);
+
+        let respond = |path: &str, authorized: bool| {
+            let mut request = Request::default();
+            if authorized {
+                request.headers.insert("Authorization".to_string(), "Bearer token".to_string());
+            }
+            let (resolver, data) = router.resolve(path).unwrap();
+            let response = resolver.resolve(&request, data);
+            (response.status.code(), std::mem::take(&mut *ORDER.lock().unwrap()))
+        };
+
+        assert_eq!(respond("/admin/users", false), (401, vec!["outer", "auth"]));
+        assert_eq!(respond("/admin/users", true), (200, vec!["outer", "auth", "route"]));
+        assert_eq!(respond("/", false), (200, vec!["outer"]));
+    }
+
+    #[test]
+    fn test_scoped_data() {
+        struct Scope(&'static str);
+
+        let mut module = Router::new();
+        module.add_data(Scope("module"));
+        module.insert("/:id", PathResolver::Placeholder("Item".to_string()));
+
+        let mut router = Router::new();
+        router.add_data(Scope("root"));
+        router.insert("/", PathResolver::Placeholder("Root".to_string()));
+        router.insert("/items", PathResolver::Router(Box::new(module)));
+
+        let (_, data) = router.resolve("/").unwrap();
+        assert_eq!(data.get::<Scope>().unwrap().0, "root");
+        assert!(data.get::<PathParams>().is_none());
+
+        let (_, data) = router.resolve("/items/5").unwrap();
+        assert_eq!(data.get::<Scope>().unwrap().0, "module");
+        assert_eq!(data.get::<PathParams>().unwrap().get_param("id"), Some("5".to_string()));
+    }
+
+    #[test]
+    fn test_shared_router_compiles_once() {
+        let shared = SharedRouter::default();
+        for index in 0..3 {
+            shared.update(|router| router.insert(&format!("/{index}"), PathResolver::Placeholder(index.to_string())));
+        }
+        assert!(shared.load().compiled.get().is_none());
+
+        shared.compile();
+        assert!(shared.load().compiled.get().is_some());
+        assert_eq!(shared.load().resolve("/2").unwrap().0, &PathResolver::Placeholder("2".to_string()));
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut module = Router::new();
+        module.set_limits(RequestLimits::new().with_max_body_size(10));
+        module.insert("/upload", PathResolver::Placeholder("Upload".to_string()));
+
+        let mut router = Router::new();
+        router.set_limits(RequestLimits::new().with_max_body_size(100));
+        router.insert("/", PathResolver::Placeholder("Root".to_string()));
+        router.insert("/files", PathResolver::Router(Box::new(module)));
+
+        // The innermost router decides, the limits are not part of the data
+        let found = router.find("/").unwrap();
+        assert_eq!(found.limits().unwrap().max_body_size, 100);
+        assert!(found.data().get::<RequestLimits>().is_none());
+        assert_eq!(router.find("/files/upload").unwrap().limits().unwrap().max_body_size, 10);
+    }
+}
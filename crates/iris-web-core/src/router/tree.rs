@@ -0,0 +1,283 @@
+use std::{collections::HashMap, iter::Filter, str::Split};
+
+use crate::{pipeline::middleware::RouterMiddlewares, server::{config::RequestLimits, path::{percent_decode, percent_decode_path}}, utils::data_container::DataContainer};
+
+use super::{constraint::Constraint, router::{PathResolver, Router}, PathParams};
+
+/// Segments of a path, empty ones are skipped so `//` and a trailing `/` don't matter.
+type Segments<'p> = Filter<Split<'p, char>, fn(&&str) -> bool>;
+
+fn segments(path: &str) -> Segments<'_> {
+    path.split('/').filter(|segment| !segment.is_empty())
+}
+
+/// Routes of a router compiled into a prefix tree of path segments.
+/// Matching a path walks the tree without allocating.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RouteTree {
+    root: Node,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Node {
+    /// Children matching a segment exactly, they are tried before the placeholder.
+    statics: HashMap<Box<str>, Node>,
+    /// Children matching segments that satisfy their constraint, the unconstrained one comes last.
+    placeholders: Vec<(Option<Constraint>, Node)>,
+    /// Route of the path ending at this node.
+    route: Option<Route>,
+    /// Route matching any rest of the path, tried after all children.
+    catch_all: Option<Route>,
+    /// Route answering the paths below the node that no route matches.
+    not_found: Option<Route>,
+}
+
+#[derive(Debug, Clone)]
+struct Param {
+    name: Box<str>,
+    constraint: Option<Constraint>,
+}
+
+#[derive(Debug, Clone)]
+struct Route {
+    resolver: PathResolver,
+    /// Data of all routers on the way to the route.
+    data: DataContainer,
+    /// Request limits of the innermost router on the way to the route that sets them.
+    limits: Option<RequestLimits>,
+    /// Placeholder of every segment of the path, `None` for static segments.
+    params: Box<[Option<Param>]>,
+    /// Name of the parameter capturing the rest of the path.
+    rest: Option<Box<str>>,
+}
+
+impl RouteTree {
+    /// Compiles the routes of the router and its sub-routers.
+    pub(crate) fn compile(router: &Router) -> Self {
+        let mut tree = Self::default();
+        tree.root.add_router(router, &DataContainer::default(), None, &mut Vec::new());
+        tree
+    }
+
+    /// Finds the route matching the path, or the `not_found` route of the innermost router on the way to it.
+    pub(crate) fn find<'r, 'p>(&'r self, path: &'p str) -> Option<RouteMatch<'r, 'p>> {
+        self.root
+            .find(segments(path))
+            .or_else(|| self.root.fallback(segments(path)))
+            .map(|route| RouteMatch { route, path })
+    }
+}
+
+impl Node {
+    fn add_router(&mut self, router: &Router, data: &DataContainer, limits: Option<&RequestLimits>, pattern: &mut Vec<Option<Param>>) {
+        let mut data = data.combine(&router.data);
+        let limits = router.limits.as_ref().or(limits);
+
+        // The middleware of the router runs after the one of the routers around it.
+        if !router.middlewares.is_empty() {
+            let mut middlewares = data.get::<RouterMiddlewares>().map(|outer| outer.0.clone()).unwrap_or_default();
+            middlewares.extend(router.middlewares.iter().cloned());
+            data.add(RouterMiddlewares(middlewares));
+        }
+
+        // The "" route belongs to the path of the router itself. It goes first, so a module
+        // mounted there doesn't take the place of routes registered next to it.
+        if let Some(resolver) = router.routes.get("") {
+            self.add_resolver(resolver, &data, limits, pattern);
+        }
+
+        for (segment, resolver) in router.routes.iter().filter(|(segment, _)| !segment.is_empty()) {
+            pattern.push(None);
+            self.statics.entry(segment.as_str().into()).or_default().add_resolver(resolver, &data, limits, pattern);
+            pattern.pop();
+        }
+
+        // Placeholders of any name share a node if their constraints are the same,
+        // each route knows the names of its own.
+        for placeholder in &router.placeholders {
+            pattern.push(Some(Param {
+                name: placeholder.name.as_str().into(),
+                constraint: placeholder.constraint.clone(),
+            }));
+            self.placeholder(&placeholder.constraint).add_resolver(&placeholder.resolver, &data, limits, pattern);
+            pattern.pop();
+        }
+
+        if let Some((name, resolver)) = &router.catch_all {
+            self.catch_all = Some(Route {
+                resolver: resolver.clone(),
+                data: data.clone(),
+                limits: limits.cloned(),
+                params: pattern.clone().into(),
+                rest: Some(name.as_str().into()),
+            });
+        }
+
+        // The router wins over a module mounted at its own path.
+        if let Some(pipeline) = &router.not_found {
+            self.not_found = Some(Route {
+                resolver: PathResolver::NotFound(pipeline.clone()),
+                data,
+                limits: limits.cloned(),
+                params: pattern.clone().into(),
+                rest: None,
+            });
+        }
+    }
+
+    fn placeholder(&mut self, constraint: &Option<Constraint>) -> &mut Node {
+        let index = match self.placeholders.iter().position(|(existing, _)| existing == constraint) {
+            Some(index) => index,
+            None => {
+                // Constrained placeholders are tried first, so they go before the unconstrained one.
+                let index = match constraint {
+                    Some(_) => self.placeholders.iter().position(|(existing, _)| existing.is_none()).unwrap_or(self.placeholders.len()),
+                    None => self.placeholders.len(),
+                };
+                self.placeholders.insert(index, (constraint.clone(), Node::default()));
+                index
+            }
+        };
+        &mut self.placeholders[index].1
+    }
+
+    fn add_resolver(&mut self, resolver: &PathResolver, data: &DataContainer, limits: Option<&RequestLimits>, pattern: &mut Vec<Option<Param>>) {
+        match resolver {
+            PathResolver::Router(router) => self.add_router(router, data, limits, pattern),
+            resolver => {
+                self.route = Some(Route {
+                    resolver: resolver.clone(),
+                    data: data.clone(),
+                    limits: limits.cloned(),
+                    params: pattern.clone().into(),
+                    rest: None,
+                });
+            }
+        }
+    }
+
+    /// Static segments are preferred, placeholders with a constraint the segment satisfies are tried
+    /// when they lead nowhere and the catch-all when none of them does.
+    fn find(&self, mut segments: Segments<'_>) -> Option<&Route> {
+        let segment = match segments.next() {
+            Some(segment) => segment,
+            None => return self.route.as_ref().or(self.catch_all.as_ref()),
+        };
+
+        if let Some(route) = self.statics.get(segment).and_then(|node| node.find(segments.clone())) {
+            return Some(route);
+        }
+
+        // Constraints check the value the controller gets, escaped slashes included.
+        let decoded = percent_decode(segment);
+        let placeholders = self.placeholders
+            .iter()
+            .filter(|(constraint, _)| constraint.as_ref().is_none_or(|constraint| constraint.matches(&decoded)));
+        for (_, node) in placeholders {
+            if let Some(route) = node.find(segments.clone()) {
+                return Some(route);
+            }
+        }
+
+        self.catch_all.as_ref()
+    }
+
+    /// Finds the deepest `not_found` route on the way to the path, following the children like `find`.
+    fn fallback(&self, mut segments: Segments<'_>) -> Option<&Route> {
+        let deeper = segments.next().and_then(|segment| {
+            let decoded = percent_decode(segment);
+            let placeholders = self.placeholders
+                .iter()
+                .filter(|(constraint, _)| constraint.as_ref().is_none_or(|constraint| constraint.matches(&decoded)))
+                .map(|(_, node)| node);
+
+            self.statics
+                .get(segment)
+                .into_iter()
+                .chain(placeholders)
+                .find_map(|node| node.fallback(segments.clone()))
+        });
+
+        deeper.or(self.not_found.as_ref())
+    }
+}
+
+/// Route matching a path, borrowed from the router.
+#[derive(Debug, Clone, Copy)]
+pub struct RouteMatch<'r, 'p> {
+    route: &'r Route,
+    path: &'p str,
+}
+
+impl<'r, 'p> RouteMatch<'r, 'p> {
+    /// Returns the resolver of the route.
+    pub fn resolver(&self) -> &'r PathResolver {
+        &self.route.resolver
+    }
+
+    /// Returns the data of the routers on the way to the route.
+    pub fn data(&self) -> &'r DataContainer {
+        &self.route.data
+    }
+
+    /// Returns the request limits of the innermost router on the way to the route that overrides them.
+    pub fn limits(&self) -> Option<&'r RequestLimits> {
+        self.route.limits.as_ref()
+    }
+
+    /// Returns the names and values of the placeholders as they appear in the path, still percent-encoded.
+    /// A catch-all comes last with the rest of the path, without the slashes around it.
+    pub fn params(&self) -> impl Iterator<Item = (&'r str, &'p str)> {
+        let rest = self.route.rest.as_deref().map(|name| (name, self.rest()));
+
+        self.route.params
+            .iter()
+            .zip(segments(self.path))
+            .filter_map(|(param, value)| param.as_ref().map(|param| (&*param.name, value)))
+            .chain(rest)
+    }
+
+    /// Returns the path after the segments matched by the route.
+    fn rest(&self) -> &'p str {
+        match segments(self.path).nth(self.route.params.len()) {
+            // The segments are slices of the path, so they tell where the rest starts.
+            Some(segment) => {
+                let start = segment.as_ptr() as usize - self.path.as_ptr() as usize;
+                self.path[start..].trim_end_matches('/')
+            }
+            None => "",
+        }
+    }
+
+    /// Returns the data for a request to the route, including its `PathParams` with decoded values.
+    pub fn into_data(self) -> DataContainer {
+        let mut data = self.route.data.clone();
+
+        if self.params().next().is_some() {
+            let path_params = PathParams::new();
+            for (name, value) in self.params().take(self.route.params.iter().flatten().count()) {
+                path_params.add_param(name.to_string(), percent_decode(value).into_owned());
+            }
+
+            // The rest keeps escaped slashes, decoding them would add segments like `..` to it.
+            if let Some(name) = &self.route.rest {
+                path_params.add_param(name.to_string(), percent_decode_path(self.rest()).into_owned());
+            }
+
+            // Values of typed placeholders are parsed once here.
+            let typed = self.route.params
+                .iter()
+                .zip(segments(self.path))
+                .filter_map(|(param, value)| param.as_ref().map(|param| (param, value)));
+            for (param, value) in typed {
+                if let Some(parsed) = param.constraint.as_ref().and_then(|constraint| constraint.parse_value(&percent_decode(value))) {
+                    path_params.add_parsed(param.name.to_string(), parsed);
+                }
+            }
+
+            data.add(path_params);
+        }
+
+        data
+    }
+}
@@ -0,0 +1,345 @@
+use std::sync::Arc;
+
+use tokio::io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+
+use crate::{pipeline::request_pipeline::RequestPipeline, router::{router::{not_found, PathResolver, SharedRouter}, Method}, utils::data_container::DataContainer};
+
+use super::{async_io::{sleep, with_timeout, AsyncTransport}, config::ServerConfig, connection::{body_limits, respond}, path::normalize_request, request::{BodyFraming, Request, RequestParseError}, response::{Response, ResponseStatus}, shutdown::ShutdownHandle};
+#[cfg(feature = "http2")]
+use super::http2;
+#[cfg(feature = "http2")]
+use tokio::io::AsyncReadExt;
+
+/// Answers a request that could not be read, the connection can't be reused afterwards.
+async fn send_parse_error(error: &RequestParseError, stream: &mut (impl AsyncWrite + Unpin)) {
+    if let Some(status) = error.status() {
+        #[cfg(debug_assertions)]
+        println!("Failed to parse request: {error}");
+
+        let mut response = Response::new().with_status(status).with_body(error.to_string());
+        response.headers.insert("Connection".to_string(), "close".to_string());
+        let _ = response.write_to_async("HTTP/1.1", false, stream).await;
+    }
+}
+
+/// Tells whether a new connection speaks HTTP/2. TLS clients negotiate it with ALPN, plain ones
+/// start with the preface, which may arrive in several reads.
+#[cfg(feature = "http2")]
+async fn is_http2(stream: &mut BufReader<AsyncTransport>) -> std::io::Result<bool> {
+    if let Some(negotiated) = stream.get_ref().negotiated_http2() {
+        return Ok(negotiated);
+    }
+    if http2::preface_missing(stream.buffer()) == 0 {
+        return Ok(http2::is_preface(stream.buffer()));
+    }
+
+    // Read the rest of the preface from the connection, then put it back in front of it.
+    let mut head = stream.buffer().to_vec();
+    stream.consume(head.len());
+    let mut chunk = [0; 16];
+    loop {
+        let missing = http2::preface_missing(&head);
+        if missing == 0 {
+            break;
+        }
+        match stream.get_mut().read(&mut chunk[..missing]).await? {
+            0 => break,
+            read => head.extend_from_slice(&chunk[..read]),
+        }
+    }
+
+    let is_preface = http2::is_preface(&head);
+    stream.get_mut().unread(head);
+    stream.fill_buf().await?;
+    Ok(is_preface)
+}
+
+/// Runs the controller of the request. Pipelines run as their own task, so a panic
+/// is answered with 500, and sync parts of them are moved to the blocking pool.
+pub(crate) async fn respond_async(resolved: Option<(&PathResolver, DataContainer)>, request: Request) -> Response {
+    let route = match resolved {
+        Some((path_resolver, path_data)) => match path_resolver.pipeline(&request.method) {
+            Some(pipeline) => Ok((pipeline.clone(), path_data, matches!(path_resolver, PathResolver::NotFound(_)))),
+            None => Err(respond(Some((path_resolver, path_data)), &request)),
+        },
+        None => Err(respond(None, &request)),
+    };
+
+    match route {
+        Ok((pipeline, path_data, is_not_found)) => {
+            let response = tokio::spawn(RequestPipeline::handle_async(pipeline, request, path_data))
+                .await
+                .unwrap_or_else(|_| Response::new().with_status(ResponseStatus::InternalServerError));
+            if is_not_found { not_found(response) } else { response }
+        }
+        Err(response) => response,
+    }
+}
+
+/// Serves requests from a single connection until the client or the server decides to close it.
+pub(crate) async fn handle_connection(stream: AsyncTransport, router: Arc<SharedRouter>, config: Arc<ServerConfig>, shutdown: ShutdownHandle) {
+    let mut stream = BufReader::new(stream);
+    let mut served = 0;
+
+    loop {
+        // Wait for the next request, the connection can be closed by a shutdown meanwhile.
+        // A connection accepted before the shutdown still gets its first request served.
+        let has_data = tokio::select! {
+            biased;
+            _ = shutdown.wait(), if served > 0 => false,
+            _ = sleep(config.keep_alive_timeout) => false,
+            filled = stream.fill_buf() => matches!(filled, Ok(buf) if !buf.is_empty()),
+        };
+        if !has_data {
+            break;
+        }
+
+        // Clients speaking HTTP/2 start with its connection preface instead of a request.
+        #[cfg(feature = "http2")]
+        if served == 0 && is_http2(&mut stream).await.unwrap_or(false) {
+            let buffered = stream.buffer().to_vec();
+            let mut stream = stream.into_inner();
+            stream.unread(buffered);
+            if let Err(_e) = http2::serve_connection(stream, router, config, &shutdown).await {
+                #[cfg(debug_assertions)]
+                println!("HTTP/2 connection failed: {_e}");
+            }
+            return;
+        }
+
+        // Parse the request line and headers, only the server-wide limits apply to them.
+        let head = with_timeout(config.limits.read_timeout, Request::parse_head_async(&mut stream, &config.limits));
+        let (mut request, framing) = match head.await {
+            Ok(head) => head,
+            Err(e) => {
+                send_parse_error(&e, &mut stream).await;
+                break;
+            }
+        };
+        served += 1;
+
+        // Paths that are not canonical may be answered without routing them.
+        let normalized = normalize_request(&mut request, config.path_normalization);
+
+        // Get the path resolver from a snapshot of the router, so changing it doesn't wait for this request.
+        let snapshot = router.load();
+        let found = match normalized {
+            Some(_) => None,
+            None => snapshot.find(&request.path),
+        };
+
+        let limits = body_limits(found.as_ref(), &request, &config);
+        let resolved = found.map(|found| (found.resolver(), found.into_data()));
+
+        // Let the client know whether it should send the body.
+        let expects_continue = request
+            .find_header("Expect")
+            .is_some_and(|expect| expect.eq_ignore_ascii_case("100-continue"));
+        if expects_continue && framing.has_body() && request.version == "HTTP/1.1" {
+            if matches!(framing, BodyFraming::ContentLength(length) if length > limits.max_body_size) {
+                send_parse_error(&RequestParseError::BodyTooLarge, &mut stream).await;
+                break;
+            }
+            let stream = stream.get_mut();
+            if stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await.is_err() || stream.flush().await.is_err() {
+                break;
+            }
+        }
+
+        if let Err(e) = with_timeout(limits.read_timeout, request.read_body_async(&mut stream, framing, &limits)).await {
+            send_parse_error(&e, &mut stream).await;
+            break;
+        }
+
+        let keep_alive = request.keep_alive()
+            && !shutdown.is_shutdown()
+            && config.max_requests_per_connection.is_none_or(|max| served < max);
+
+        let version = request.version.clone();
+        let head_only = request.method == Method::HEAD;
+        let mut response = match normalized {
+            Some(response) => response,
+            None => respond_async(resolved, request).await,
+        };
+
+        let keep_alive = keep_alive && !response.is_close_delimited(&version);
+
+        // Tell the client what will happen with the connection.
+        let connection = if keep_alive { "keep-alive" } else { "close" };
+        response.headers.insert("Connection".to_string(), connection.to_string());
+
+        let sent = with_timeout(limits.write_timeout, async {
+            response.write_to_async(&version, head_only, &mut stream).await.map_err(RequestParseError::Io)
+        });
+        if sent.await.is_err() || !keep_alive {
+            break;
+        }
+    }
+
+    let _ = stream.shutdown().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::sync::{mpsc, watch};
+
+    use super::*;
+    use crate::{pipeline::{controller::{ConfigurableController, Data}, request_pipeline::PipelineData}, router::Method, server::{http_server::HttpServer, response::UnserializedBody, test_client::{get_async, send_async}}};
+
+    /// How long the tests wait for a request that should overlap with another one.
+    const OVERLAP_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// State of a single test, the slow controllers only succeed when `/fast` is
+    /// served while they are still running.
+    struct Overlap {
+        started: mpsc::UnboundedSender<&'static str>,
+        fast_served: watch::Sender<bool>,
+    }
+
+    impl Overlap {
+        /// Waits until `/fast` was served, returns false when it took too long.
+        async fn wait_for_fast(&self) -> bool {
+            let mut served = self.fast_served.subscribe();
+            let waited = tokio::time::timeout(OVERLAP_TIMEOUT, served.wait_for(|served| *served)).await;
+            waited.is_ok()
+        }
+    }
+
+    async fn slow(overlap: Data<'_, Overlap>) -> UnserializedBody {
+        overlap.started.send("slow").unwrap();
+        match overlap.wait_for_fast().await {
+            true => UnserializedBody(b"Slow".to_vec()),
+            false => UnserializedBody(b"Timed out".to_vec()),
+        }
+    }
+
+    async fn fast(overlap: Data<'_, Overlap>) -> UnserializedBody {
+        overlap.fast_served.send_replace(true);
+        UnserializedBody(b"Fast".to_vec())
+    }
+
+    fn blocking(overlap: Data<'_, Overlap>) -> UnserializedBody {
+        overlap.started.send("blocking").unwrap();
+        match tokio::runtime::Handle::current().block_on(overlap.wait_for_fast()) {
+            true => UnserializedBody(b"Blocking".to_vec()),
+            false => UnserializedBody(b"Timed out".to_vec()),
+        }
+    }
+
+    async fn add_name(pipeline: &mut PipelineData) -> Option<UnserializedBody> {
+        pipeline.add_data("John".to_string());
+        None
+    }
+
+    fn greet(name: Data<'_, String>) -> UnserializedBody {
+        UnserializedBody(format!("Hello {}!", name.as_str()).into_bytes())
+    }
+
+    #[tokio::test]
+    async fn test_async_controllers() {
+        let (started, mut started_receiver) = mpsc::unbounded_channel();
+        let mut server = HttpServer::new();
+        server
+            .add_data(Overlap { started, fast_served: watch::channel(false).0 })
+            .add_route("/slow", Method::GET, slow)
+            .add_route("/fast", Method::GET, fast)
+            .add_route("/blocking", Method::GET, blocking)
+            .add_route("/greet", Method::GET, greet.with_middleware(add_name));
+        let address = server.bind(("127.0.0.1", 0)).unwrap();
+        let shutdown = server.shutdown_handle();
+        let running = tokio::spawn(async move { server.run_async().await });
+
+        // Slow requests, async or blocking, don't hold up other ones.
+        let slow_response = tokio::spawn(get_async(address, "/slow"));
+        let blocking_response = tokio::spawn(get_async(address, "/blocking"));
+        for _ in 0..2 {
+            tokio::time::timeout(OVERLAP_TIMEOUT, started_receiver.recv())
+                .await
+                .expect("the slow requests were not started")
+                .unwrap();
+        }
+        assert!(get_async(address, "/fast").await.ends_with("\r\n\r\nFast"));
+
+        // They only complete with their body when `/fast` was served while they were running.
+        assert!(slow_response.await.unwrap().ends_with("\r\n\r\nSlow"));
+        assert!(blocking_response.await.unwrap().ends_with("\r\n\r\nBlocking"));
+
+        // Async middleware runs before a sync controller.
+        assert!(get_async(address, "/greet").await.ends_with("\r\n\r\nHello John!"));
+        assert!(get_async(address, "/missing").await.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        shutdown.shutdown();
+        running.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_async_router_middleware() {
+        fn rename(pipeline: &mut PipelineData) -> Option<UnserializedBody> {
+            pipeline.add_data("Jane".to_string());
+            None
+        }
+
+        let mut server = HttpServer::new();
+        server
+            .add_middleware(add_name)
+            .add_route("/greet", Method::GET, greet)
+            .add_route("/renamed", Method::GET, greet.with_middleware(rename));
+        let address = server.bind(("127.0.0.1", 0)).unwrap();
+        let shutdown = server.shutdown_handle();
+        let running = tokio::spawn(async move { server.run_async().await });
+
+        // The middleware of the server runs before the one of the route.
+        assert!(get_async(address, "/greet").await.ends_with("\r\n\r\nHello John!"));
+        assert!(get_async(address, "/renamed").await.ends_with("\r\n\r\nHello Jane!"));
+
+        shutdown.shutdown();
+        running.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_async_not_found() {
+        async fn missing() -> UnserializedBody {
+            UnserializedBody(b"Nothing here".to_vec())
+        }
+
+        let mut server = HttpServer::new();
+        server.not_found(missing);
+        let address = server.bind(("127.0.0.1", 0)).unwrap();
+        let shutdown = server.shutdown_handle();
+        let running = tokio::spawn(async move { server.run_async().await });
+
+        let response = get_async(address, "/missing").await;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(response.ends_with("\r\n\r\nNothing here"));
+
+        shutdown.shutdown();
+        running.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_async_parsing() {
+        let mut server = HttpServer::new();
+        server.add_route("/echo", Method::POST, |request: &Request| UnserializedBody(request.body.clone()));
+        let address = server.bind(("127.0.0.1", 0)).unwrap();
+        let shutdown = server.shutdown_handle();
+        let running = tokio::spawn(async move { server.run_async().await });
+
+        // Pipelined requests over a single connection, the second one chunked.
+        let response = send_async(address, concat!(
+            "POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nHello",
+            "POST /echo HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n",
+            "6\r\nWorld!\r\n0\r\nX-Trailer: yes\r\n\r\n",
+        )).await;
+        assert_eq!(response.matches("HTTP/1.1 200 OK\r\n").count(), 2);
+        assert!(response.contains("\r\n\r\nHello"));
+        assert!(response.ends_with("\r\n\r\nWorld!"));
+
+        let response = send_async(address, "GET /echo HTTP/1.1\r\nHost: localhost\r\nBroken header\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+
+        shutdown.shutdown();
+        running.await.unwrap();
+    }
+}
@@ -0,0 +1,129 @@
+use std::{future::Future, pin::Pin, task::{Context, Poll}, time::Duration};
+
+use tokio::{io::{AsyncRead, AsyncWrite, ReadBuf}, net::TcpStream};
+
+use super::request::RequestParseError;
+
+/// Sleeps for the duration, or forever if there is none.
+pub(crate) async fn sleep(duration: Option<Duration>) {
+    match duration {
+        Some(duration) => tokio::time::sleep(duration).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Runs the future with a timeout, reading the request is given up on once it elapses.
+pub(crate) async fn with_timeout<T>(timeout: Option<Duration>, future: impl Future<Output = Result<T, RequestParseError>>) -> Result<T, RequestParseError> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, future)
+            .await
+            .unwrap_or(Err(RequestParseError::Timeout { idle: false })),
+        None => future.await,
+    }
+}
+
+/// Byte stream of an async connection, either plain TCP or TLS on top of it.
+enum AsyncStream {
+    Plain(TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<tokio_rustls::server::TlsStream<TcpStream>>),
+}
+
+/// Async view of a connection, optionally starting with data that was already read from it.
+pub(crate) struct AsyncTransport {
+    buffered: Vec<u8>,
+    position: usize,
+    stream: AsyncStream,
+}
+
+impl AsyncTransport {
+    /// Wraps an accepted plain connection.
+    pub(crate) fn plain(tcp: TcpStream) -> Self {
+        Self::new(AsyncStream::Plain(tcp))
+    }
+
+    /// Wraps a connection that finished its TLS handshake.
+    #[cfg(feature = "tls")]
+    pub(crate) fn tls(stream: tokio_rustls::server::TlsStream<TcpStream>) -> Self {
+        Self::new(AsyncStream::Tls(Box::new(stream)))
+    }
+
+    fn new(stream: AsyncStream) -> Self {
+        Self {
+            buffered: Vec::new(),
+            position: 0,
+            stream,
+        }
+    }
+
+    /// Puts data that was already read from the connection back, it is returned by the next reads.
+    #[cfg(feature = "http2")]
+    pub(crate) fn unread(&mut self, data: Vec<u8>) {
+        self.buffered = [&data[..], &self.buffered[self.position..]].concat();
+        self.position = 0;
+    }
+
+    /// Returns whether the client negotiated HTTP/2 with ALPN, `None` for plain connections.
+    #[cfg(feature = "http2")]
+    pub(crate) fn negotiated_http2(&self) -> Option<bool> {
+        match &self.stream {
+            AsyncStream::Plain(_) => None,
+            #[cfg(feature = "tls")]
+            AsyncStream::Tls(stream) => Some(stream.get_ref().1.alpn_protocol() == Some(b"h2")),
+        }
+    }
+}
+
+/// Moves a connection of the blocking server onto the runtime.
+#[cfg(feature = "http2")]
+pub(crate) fn from_std(tcp: std::net::TcpStream) -> std::io::Result<TcpStream> {
+    tcp.set_read_timeout(None)?;
+    tcp.set_write_timeout(None)?;
+    tcp.set_nonblocking(true)?;
+    TcpStream::from_std(tcp)
+}
+
+impl AsyncRead for AsyncTransport {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+
+        if this.position < this.buffered.len() {
+            let read = buf.remaining().min(this.buffered.len() - this.position);
+            buf.put_slice(&this.buffered[this.position..this.position + read]);
+            this.position += read;
+            return Poll::Ready(Ok(()));
+        }
+
+        match &mut this.stream {
+            AsyncStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(feature = "tls")]
+            AsyncStream::Tls(stream) => Pin::new(stream.as_mut()).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for AsyncTransport {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        match &mut self.get_mut().stream {
+            AsyncStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(feature = "tls")]
+            AsyncStream::Tls(stream) => Pin::new(stream.as_mut()).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match &mut self.get_mut().stream {
+            AsyncStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(feature = "tls")]
+            AsyncStream::Tls(stream) => Pin::new(stream.as_mut()).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match &mut self.get_mut().stream {
+            AsyncStream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(feature = "tls")]
+            AsyncStream::Tls(stream) => Pin::new(stream.as_mut()).poll_shutdown(cx),
+        }
+    }
+}
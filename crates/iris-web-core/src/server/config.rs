@@ -0,0 +1,225 @@
+use std::time::Duration;
+
+/// Configuration of the HTTP server.
+/// Socket options only apply to addresses bound after the config was set.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Number of worker threads serving connections, at least one is started.
+    /// With the `http2` feature, `run` serves each HTTP/2 connection on a runtime of its own that
+    /// keeps a worker until the connection is closed. `run_async` serves them on its runtime instead.
+    pub workers: usize,
+    /// Whether `SO_REUSEADDR` is set on the listening sockets.
+    pub reuse_address: bool,
+    /// Whether `SO_REUSEPORT` is set on the listening sockets, only supported on unix.
+    pub reuse_port: bool,
+    /// Maximum number of pending connections waiting to be accepted.
+    pub backlog: i32,
+    /// Whether `TCP_NODELAY` is set on accepted connections.
+    pub tcp_nodelay: bool,
+    /// Whether IPv6 listening sockets only accept IPv6 connections, set it to bind IPv4 and IPv6
+    /// listeners on the same port. `None` keeps the default of the OS, which is usually dual-stack.
+    pub only_v6: Option<bool>,
+    /// How long an idle keep-alive connection is kept open while waiting for the next request.
+    /// `None` keeps idle connections open until the client closes them.
+    /// On unix idle connections wait without holding a worker, elsewhere each one keeps its worker busy.
+    pub keep_alive_timeout: Option<Duration>,
+    /// Maximum number of requests served over a single connection.
+    /// `None` means that there is no limit.
+    pub max_requests_per_connection: Option<usize>,
+    /// How long the server waits for in-flight requests when shutting down.
+    pub shutdown_timeout: Duration,
+    /// Limits applied while reading requests.
+    pub limits: RequestLimits,
+    /// How request paths that are not in their canonical form are handled.
+    pub path_normalization: PathNormalization,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            workers: 4,
+            reuse_address: true,
+            reuse_port: false,
+            backlog: 128,
+            tcp_nodelay: true,
+            only_v6: None,
+            keep_alive_timeout: Some(Duration::from_secs(5)),
+            max_requests_per_connection: Some(100),
+            shutdown_timeout: Duration::from_secs(30),
+            limits: RequestLimits::default(),
+            path_normalization: PathNormalization::default(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Creates a new config with the default values.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of workers to start, there has to be one to serve the connections.
+    pub(crate) fn worker_count(&self) -> usize {
+        self.workers.max(1)
+    }
+
+    /// Sets the number of worker threads serving connections, zero is treated as one.
+    pub fn with_workers(mut self, workers: usize) -> Self {
+        self.workers = workers;
+        self
+    }
+
+    /// Sets whether `SO_REUSEADDR` is set on the listening sockets.
+    pub fn with_reuse_address(mut self, reuse_address: bool) -> Self {
+        self.reuse_address = reuse_address;
+        self
+    }
+
+    /// Sets whether `SO_REUSEPORT` is set on the listening sockets, this is ignored on platforms without it.
+    pub fn with_reuse_port(mut self, reuse_port: bool) -> Self {
+        self.reuse_port = reuse_port;
+        self
+    }
+
+    /// Sets the maximum number of pending connections waiting to be accepted.
+    pub fn with_backlog(mut self, backlog: i32) -> Self {
+        self.backlog = backlog;
+        self
+    }
+
+    /// Sets whether `TCP_NODELAY` is set on accepted connections.
+    pub fn with_tcp_nodelay(mut self, tcp_nodelay: bool) -> Self {
+        self.tcp_nodelay = tcp_nodelay;
+        self
+    }
+
+    /// Sets whether IPv6 listening sockets only accept IPv6 connections.
+    pub fn with_only_v6(mut self, only_v6: bool) -> Self {
+        self.only_v6 = Some(only_v6);
+        self
+    }
+
+    /// Sets the idle timeout of keep-alive connections.
+    pub fn with_keep_alive_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.keep_alive_timeout = timeout;
+        self
+    }
+
+    /// Sets the maximum number of requests served over a single connection.
+    pub fn with_max_requests_per_connection(mut self, max: Option<usize>) -> Self {
+        self.max_requests_per_connection = max;
+        self
+    }
+
+    /// Sets how long the server waits for in-flight requests when shutting down.
+    pub fn with_shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Sets the limits applied while reading requests.
+    pub fn with_limits(mut self, limits: RequestLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Sets how request paths that are not in their canonical form are handled.
+    pub fn with_path_normalization(mut self, policy: PathNormalization) -> Self {
+        self.path_normalization = policy;
+        self
+    }
+}
+
+/// How the server handles request paths that are not in their canonical form,
+/// like `/a//b/`, `/a/./b` or `/a/%62`. See `server::path::canonical_path`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PathNormalization {
+    /// Only canonical paths are routed, other ones are answered with 404.
+    Strict,
+    /// Other paths are answered with a 308 redirect to the canonical one.
+    Redirect,
+    /// Other paths are routed as if the canonical one was requested.
+    #[default]
+    Lenient,
+}
+
+/// Limits applied while reading a request, requests exceeding them are rejected.
+///
+/// The server-wide limits are set with `ServerConfig::with_limits`. A router or module
+/// can override them for its routes with `Router::set_limits`, and a single route with
+/// `ConfigurableController::with_limits`. The request line and header limits are checked
+/// before the route is known, so only the server-wide values apply to them.
+#[derive(Debug, Clone)]
+pub struct RequestLimits {
+    /// Maximum length of the request line in bytes, longer ones are answered with 414.
+    pub max_request_line: usize,
+    /// Maximum size of all headers in bytes, larger ones are answered with 431.
+    pub max_header_bytes: usize,
+    /// Maximum number of headers, more are answered with 431.
+    pub max_header_count: usize,
+    /// Maximum size of the body in bytes, after decoding the chunked transfer encoding.
+    /// Larger bodies are answered with 413.
+    pub max_body_size: usize,
+    /// How long reading the request may take once it started, slower clients get 408.
+    /// `None` means that there is no limit.
+    pub read_timeout: Option<Duration>,
+    /// How long writing a response may block before the connection is dropped.
+    /// `None` means that there is no limit.
+    pub write_timeout: Option<Duration>,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        Self {
+            max_request_line: 8 * 1024,
+            max_header_bytes: 64 * 1024,
+            max_header_count: 100,
+            max_body_size: 16 * 1024 * 1024,
+            read_timeout: Some(Duration::from_secs(30)),
+            write_timeout: Some(Duration::from_secs(30)),
+        }
+    }
+}
+
+impl RequestLimits {
+    /// Creates new limits with the default values.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the maximum length of the request line in bytes.
+    pub fn with_max_request_line(mut self, max: usize) -> Self {
+        self.max_request_line = max;
+        self
+    }
+
+    /// Sets the maximum size of all headers in bytes.
+    pub fn with_max_header_bytes(mut self, max: usize) -> Self {
+        self.max_header_bytes = max;
+        self
+    }
+
+    /// Sets the maximum number of headers.
+    pub fn with_max_header_count(mut self, max: usize) -> Self {
+        self.max_header_count = max;
+        self
+    }
+
+    /// Sets the maximum size of the body in bytes.
+    pub fn with_max_body_size(mut self, max: usize) -> Self {
+        self.max_body_size = max;
+        self
+    }
+
+    /// Sets how long reading a request may take.
+    pub fn with_read_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    /// Sets how long writing a response may block.
+    pub fn with_write_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.write_timeout = timeout;
+        self
+    }
+}
@@ -0,0 +1,272 @@
+use std::{sync::{Arc, Mutex}, io::{BufReader, BufRead, Read, Write, ErrorKind}, panic::{self, AssertUnwindSafe}, time::{Duration, Instant}};
+
+use crate::{router::{router::{PathResolver, SharedRouter}, tree::RouteMatch}, utils::data_container::DataContainer};
+
+use super::{config::{ServerConfig, RequestLimits}, path::normalize_request, request::{Request, RequestParseError, BodyFraming}, response::{Response, ResponseStatus}, idle::IdleConnections, shutdown::ShutdownHandle, transport::Transport};
+#[cfg(feature = "http2")]
+use super::http2;
+
+/// Read side of a connection that fails reads once its deadline has passed.
+struct DeadlineStream {
+    stream: Arc<Mutex<Transport>>,
+    deadline: Option<Instant>,
+    /// Data that was put back, it is read before the connection.
+    unread: Vec<u8>,
+}
+
+impl DeadlineStream {
+    fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.deadline = timeout.map(|timeout| Instant::now() + timeout);
+    }
+}
+
+impl Read for DeadlineStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if !self.unread.is_empty() {
+            let read = buf.len().min(self.unread.len());
+            buf[..read].copy_from_slice(&self.unread[..read]);
+            self.unread.drain(..read);
+            return Ok(read);
+        }
+
+        let timeout = match self.deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Err(ErrorKind::TimedOut.into());
+                }
+                Some(remaining)
+            }
+            None => None,
+        };
+
+        let mut stream = self.stream.lock().unwrap();
+        stream.tcp().set_read_timeout(timeout)?;
+        stream.read(buf)
+    }
+}
+
+/// Tells whether a new connection speaks HTTP/2. TLS clients negotiate it with ALPN, plain ones
+/// start with the preface, which may arrive in several reads.
+#[cfg(feature = "http2")]
+fn is_http2(buf_reader: &mut BufReader<DeadlineStream>) -> std::io::Result<bool> {
+    if let Some(negotiated) = buf_reader.get_ref().stream.lock().unwrap().negotiated_http2() {
+        return Ok(negotiated);
+    }
+    if http2::preface_missing(buf_reader.buffer()) == 0 {
+        return Ok(http2::is_preface(buf_reader.buffer()));
+    }
+
+    // Read the rest of the preface from the connection, then put it back in front of it.
+    let mut head = buf_reader.buffer().to_vec();
+    buf_reader.consume(head.len());
+    let mut chunk = [0; 16];
+    loop {
+        let missing = http2::preface_missing(&head);
+        if missing == 0 {
+            break;
+        }
+        match buf_reader.get_mut().read(&mut chunk[..missing])? {
+            0 => break,
+            read => head.extend_from_slice(&chunk[..read]),
+        }
+    }
+
+    let is_preface = http2::is_preface(&head);
+    buf_reader.get_mut().unread = head;
+    buf_reader.fill_buf()?;
+    Ok(is_preface)
+}
+
+/// Reads the ClientHello of a new TLS connection. Clients choosing h2 are served over HTTP/2 on
+/// a runtime, the others get their handshake done by the worker and are returned to it.
+#[cfg(all(feature = "tls", feature = "http2"))]
+fn accept_tls(stream: Arc<Mutex<Transport>>, router: &Arc<SharedRouter>, config: &ServerConfig, shutdown: &ShutdownHandle) -> Option<Arc<Mutex<Transport>>> {
+    if !matches!(*stream.lock().unwrap(), Transport::TlsPending(..)) {
+        return Some(stream);
+    }
+    let Ok(Transport::TlsPending(mut tcp, tls)) = Arc::try_unwrap(stream).map(|stream| stream.into_inner().unwrap_or_else(|e| e.into_inner())) else {
+        return None;
+    };
+
+    tcp.set_read_timeout(config.limits.read_timeout).ok()?;
+    let accepted = match super::tls::read_client_hello(&mut tcp) {
+        Ok(accepted) => accepted,
+        Err(_e) => {
+            #[cfg(debug_assertions)]
+            println!("Handshake failed: {_e}");
+            return None;
+        }
+    };
+
+    if super::tls::chooses_http2(&accepted, &tls) {
+        http2::serve_tls(tcp, accepted, tls, router.clone(), config, shutdown);
+        return None;
+    }
+
+    let connection = accepted.into_connection(tls).ok()?;
+    Some(Arc::new(Mutex::new(Transport::Tls(Box::new(rustls::StreamOwned::new(connection, tcp))))))
+}
+
+/// Answers a request that could not be read, the connection can't be reused afterwards.
+fn send_parse_error(error: &RequestParseError, stream: &Mutex<Transport>) {
+    if let Some(status) = error.status() {
+        #[cfg(debug_assertions)]
+        println!("Failed to parse request: {error}");
+
+        let mut response = Response::new().with_status(status).with_body(error.to_string());
+        response.headers.insert("Connection".to_string(), "close".to_string());
+        let _ = response.write_to("HTTP/1.1", false, &mut *stream.lock().unwrap());
+    }
+}
+
+/// Picks the limits of the request body, the route, its router or the server decide about them, in that order.
+pub(crate) fn body_limits(found: Option<&RouteMatch<'_, '_>>, request: &Request, config: &ServerConfig) -> RequestLimits {
+    found
+        .and_then(|found| found.resolver().limits(&request.method).or_else(|| found.limits().cloned()))
+        .unwrap_or_else(|| config.limits.clone())
+}
+
+/// Runs the controller of the request.
+pub(crate) fn respond(resolved: Option<(&PathResolver, DataContainer)>, request: &Request) -> Response {
+    // A panicking controller must not take the worker down with it.
+    let response = panic::catch_unwind(AssertUnwindSafe(|| {
+        match resolved {
+            Some((path_resolver, path_data)) => path_resolver.resolve(request, path_data),
+            None => {
+                println!("No path resolver found for path: {}", request.path);
+                Response::default()
+            }
+        }
+    }));
+
+    response.unwrap_or_else(|_| Response::new().with_status(ResponseStatus::InternalServerError))
+}
+
+/// Serves requests from a single connection until the client or the server decides to close it.
+/// While waiting for the next request the connection is parked in `idle`, which frees the worker.
+pub(crate) fn handle_connection(mut stream: Arc<Mutex<Transport>>, mut served: usize, router: Arc<SharedRouter>, config: &ServerConfig, shutdown: &ShutdownHandle, idle: &IdleConnections) {
+    #[cfg(debug_assertions)]
+    if served == 0 {
+        println!("New connection: {:?}", stream.lock().unwrap().tcp().peer_addr());
+    }
+
+    #[cfg(all(feature = "tls", feature = "http2"))]
+    if served == 0 {
+        stream = match accept_tls(stream, &router, config, shutdown) {
+            Some(stream) => stream,
+            None => return,
+        };
+    }
+
+    let mut buf_reader = BufReader::new(DeadlineStream { stream: stream.clone(), deadline: None, unread: Vec::new() });
+
+    loop {
+        // Wait for the next request, the connection can be closed by a shutdown meanwhile.
+        // A connection accepted before the shutdown still gets its first request served.
+        buf_reader.get_mut().set_timeout(config.keep_alive_timeout);
+        let idle_id = shutdown.register_idle(stream.lock().unwrap().tcp());
+        if idle_id.is_none() && served > 0 {
+            break;
+        }
+        let has_data = buf_reader.fill_buf().map(|buf| !buf.is_empty());
+        if let Some(idle_id) = idle_id {
+            shutdown.unregister_idle(idle_id);
+        }
+        if !matches!(has_data, Ok(true)) {
+            break;
+        }
+
+        // Clients speaking HTTP/2 start with its connection preface instead of a request.
+        #[cfg(feature = "http2")]
+        if served == 0 && is_http2(&mut buf_reader).unwrap_or(false) {
+            let buffered = buf_reader.buffer().to_vec();
+            drop(buf_reader);
+            // TLS clients choosing h2 were handed over before their handshake, so only plain ones are left.
+            if let Ok(Transport::Plain(stream)) = Arc::try_unwrap(stream).map(|stream| stream.into_inner().unwrap_or_else(|e| e.into_inner())) {
+                http2::serve(stream, buffered, router, config, shutdown);
+            }
+            return;
+        }
+
+        // Parse the request line and headers, only the server-wide limits apply to them.
+        buf_reader.get_mut().set_timeout(config.limits.read_timeout);
+        let (mut request, framing) = match Request::parse_head(&mut buf_reader, &config.limits) {
+            Ok(head) => head,
+            Err(e) => {
+                send_parse_error(&e, &stream);
+                break;
+            }
+        };
+        served += 1;
+
+        // Paths that are not canonical may be answered without routing them.
+        let normalized = normalize_request(&mut request, config.path_normalization);
+
+        // Get the path resolver from a snapshot of the router, so changing it doesn't wait for this request.
+        let snapshot = router.load();
+        let found = match normalized {
+            Some(_) => None,
+            None => snapshot.find(&request.path),
+        };
+
+        let limits = body_limits(found.as_ref(), &request, config);
+        let resolved = found.map(|found| (found.resolver(), found.into_data()));
+
+        // Let the client know whether it should send the body.
+        let expects_continue = request
+            .find_header("Expect")
+            .is_some_and(|expect| expect.eq_ignore_ascii_case("100-continue"));
+        if expects_continue && framing.has_body() && request.version == "HTTP/1.1" {
+            if matches!(framing, BodyFraming::ContentLength(length) if length > limits.max_body_size) {
+                send_parse_error(&RequestParseError::BodyTooLarge, &stream);
+                break;
+            }
+            let mut stream_write = stream.lock().unwrap();
+            if stream_write.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").and_then(|_| stream_write.flush()).is_err() {
+                break;
+            }
+        }
+
+        buf_reader.get_mut().set_timeout(limits.read_timeout);
+        if let Err(e) = request.read_body(&mut buf_reader, framing, &limits) {
+            send_parse_error(&e, &stream);
+            break;
+        }
+        request.stream = Some(stream.clone());
+
+        let keep_alive = request.keep_alive()
+            && !shutdown.is_shutdown()
+            && config.max_requests_per_connection.is_none_or(|max| served < max);
+
+        let mut response = match normalized {
+            Some(response) => response,
+            None => respond(resolved, &request),
+        };
+
+        let keep_alive = keep_alive && !response.is_close_delimited(&request.version);
+
+        // Tell the client what will happen with the connection.
+        let connection = if keep_alive { "keep-alive" } else { "close" };
+        response.headers.insert("Connection".to_string(), connection.to_string());
+
+        if stream.lock().unwrap().tcp().set_write_timeout(limits.write_timeout).is_err() {
+            break;
+        }
+        if response.send_response(&request).is_err() || !keep_alive {
+            break;
+        }
+
+        // Without a pipelined request the connection is idle, it waits in the poller instead of holding the worker.
+        if buf_reader.buffer().is_empty() && !stream.lock().unwrap().has_buffered_data() {
+            drop(buf_reader);
+            stream = match idle.park(stream, served, config.keep_alive_timeout) {
+                Ok(()) => return,
+                Err(stream) => stream,
+            };
+            buf_reader = BufReader::new(DeadlineStream { stream: stream.clone(), deadline: None, unread: Vec::new() });
+        }
+    }
+
+    stream.lock().unwrap().close();
+}
@@ -0,0 +1,158 @@
+use std::{net::SocketAddr, sync::Arc, thread::JoinHandle};
+
+use crate::router::router::{Router, SharedRouter};
+
+use super::shutdown::ShutdownHandle;
+
+/// Handle to a server running in the background, created by `HttpServer::spawn`.
+/// Dropping the handle shuts the server down and waits for it to stop.
+#[derive(Debug)]
+pub struct ServerHandle {
+    pub(crate) addresses: Vec<SocketAddr>,
+    pub(crate) router: Arc<SharedRouter>,
+    pub(crate) shutdown: ShutdownHandle,
+    pub(crate) thread: Option<JoinHandle<()>>,
+}
+
+impl ServerHandle {
+    /// Returns the first address the server is bound to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addresses[0]
+    }
+
+    /// Returns all addresses the server is bound to.
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.addresses
+    }
+
+    /// Replaces the router of the running server, requests in flight finish with the old one.
+    pub fn set_router(&self, router: Router) {
+        self.router.store(router);
+    }
+
+    /// Changes the router of the running server, requests in flight finish with the old one.
+    pub fn update_router(&self, f: impl FnOnce(&mut Router)) {
+        self.router.update(f);
+        self.router.compile();
+    }
+
+    /// Returns a handle that can be used to stop the server from another thread.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Requests the server to shut down, use `join` to wait until it stops.
+    pub fn shutdown(&self) {
+        self.shutdown.shutdown();
+    }
+
+    /// Waits until the server stops.
+    pub fn join(mut self) {
+        self.wait();
+    }
+
+    fn wait(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            // A panic was already reported by the server thread.
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        if self.thread.is_some() {
+            self.shutdown();
+            self.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::{Read, Write}, net::TcpStream, sync::{mpsc, Mutex}, time::Duration};
+
+    use crate::{router::{router::Router, Method}, server::{config::ServerConfig, http_server::HttpServer, response::UnserializedBody, test_client::{get, read_response}}};
+
+    #[test]
+    fn test_spawned_server() {
+        let mut server = HttpServer::new();
+        server.add_route("/hello", Method::GET, || UnserializedBody(b"Hello!".to_vec()));
+        server.bind(("127.0.0.1", 0)).unwrap();
+        let handle = server.spawn();
+
+        // Two requests over the same connection
+        let mut stream = TcpStream::connect(handle.local_addr()).unwrap();
+        for _ in 0..2 {
+            stream.write_all(b"GET /hello HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+            let response = read_response(&mut stream);
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+            assert!(response.ends_with("\r\n\r\nHello!"));
+        }
+
+        handle.shutdown();
+        handle.join();
+
+        // The idle connection is closed by the shutdown
+        assert_eq!(stream.read(&mut [0; 16]).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_idle_connections_free_workers() {
+        let mut server = HttpServer::new();
+        server.with_config(ServerConfig::new().with_workers(1));
+        server.add_route("/hello", Method::GET, || UnserializedBody(b"Hello!".to_vec()));
+        server.bind(("127.0.0.1", 0)).unwrap();
+        let handle = server.spawn();
+
+        // More idle keep-alive connections than workers
+        let mut idle = (0..3)
+            .map(|_| {
+                let mut stream = TcpStream::connect(handle.local_addr()).unwrap();
+                stream.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+                stream.write_all(b"GET /hello HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+                assert!(read_response(&mut stream).ends_with("\r\n\r\nHello!"));
+                stream
+            })
+            .collect::<Vec<_>>();
+
+        // A new connection is served before the keep-alive timeout ends, and so are the idle ones
+        idle.push(TcpStream::connect(handle.local_addr()).unwrap());
+        for stream in &mut idle {
+            stream.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+            stream.write_all(b"GET /hello HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+            assert!(read_response(stream).ends_with("\r\n\r\nHello!"));
+        }
+    }
+
+    #[test]
+    fn test_router_swap() {
+        // The slow request only finishes once it is released.
+        let (started, slow_started) = mpsc::channel();
+        let (release, released) = mpsc::channel();
+        let released = Mutex::new(released);
+        let mut server = HttpServer::new();
+        server.add_route("/slow", Method::GET, move || {
+            started.send(()).unwrap();
+            released.lock().unwrap().recv_timeout(Duration::from_secs(5)).unwrap();
+            UnserializedBody(b"Slow".to_vec())
+        });
+        let address = server.bind(("127.0.0.1", 0)).unwrap();
+        let handle = server.spawn();
+
+        let slow = std::thread::spawn(move || get(address, "/slow"));
+        slow_started.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        // Changing the router doesn't wait for the request in flight
+        handle.update_router(|router| {
+            router.add_route("/new", Method::GET, || UnserializedBody(b"New".to_vec()));
+        });
+        assert!(get(address, "/new").ends_with("\r\n\r\nNew"));
+
+        release.send(()).unwrap();
+        assert!(slow.join().unwrap().ends_with("\r\n\r\nSlow"));
+
+        handle.set_router(Router::new());
+        assert!(get(address, "/new").starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
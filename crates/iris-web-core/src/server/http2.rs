@@ -0,0 +1,504 @@
+use std::{collections::HashMap, future::{poll_fn, Future}, net::TcpStream, sync::Arc};
+
+use bytes::Bytes;
+use h2::{server::SendResponse, RecvStream};
+
+use crate::router::router::SharedRouter;
+
+use super::{async_connection::respond_async, async_io::{self, sleep, with_timeout, AsyncTransport}, config::{ServerConfig, RequestLimits}, connection::body_limits, path::normalize_request, request::{Request, RequestParseError}, response::{Response, StreamingBody}, shutdown::ShutdownHandle};
+
+/// Headers that are specific to HTTP/1 connections and not allowed in HTTP/2.
+const CONNECTION_HEADERS: [&str; 5] = ["connection", "keep-alive", "proxy-connection", "transfer-encoding", "upgrade"];
+
+/// Start of the HTTP/2 connection preface, enough to tell it apart from HTTP/1 requests.
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n";
+
+/// Returns whether the data read from a new connection starts with the HTTP/2 connection preface.
+pub(crate) fn is_preface(buffer: &[u8]) -> bool {
+    buffer.starts_with(PREFACE)
+}
+
+/// Returns how many more bytes are needed to tell whether the data starts with the preface.
+pub(crate) fn preface_missing(buffer: &[u8]) -> usize {
+    match buffer.len() < PREFACE.len() && PREFACE.starts_with(buffer) {
+        true => PREFACE.len() - buffer.len(),
+        false => 0,
+    }
+}
+
+/// Serves a plain HTTP/2 connection of the blocking server until the client or the server closes it.
+/// `buffered` holds the data that was already read from the connection, starting with the preface.
+pub(crate) fn serve(stream: TcpStream, buffered: Vec<u8>, router: Arc<SharedRouter>, config: &ServerConfig, shutdown: &ShutdownHandle) {
+    block_on_connection(config, |config| async move {
+        let mut io = AsyncTransport::plain(async_io::from_std(stream)?);
+        io.unread(buffered);
+        serve_connection(io, router, config, shutdown).await.map_err(std::io::Error::other)
+    });
+}
+
+/// Serves a TLS connection of the blocking server whose client chose h2, starting with its handshake.
+#[cfg(feature = "tls")]
+pub(crate) fn serve_tls(
+    stream: TcpStream,
+    accepted: rustls::server::Accepted,
+    tls: Arc<rustls::ServerConfig>,
+    router: Arc<SharedRouter>,
+    config: &ServerConfig,
+    shutdown: &ShutdownHandle,
+) {
+    block_on_connection(config, |config| async move {
+        let handshake = tokio_rustls::StartHandshake::from_parts(accepted, async_io::from_std(stream)?).into_stream(tls);
+        let stream = match config.limits.read_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, handshake).await.map_err(|_| std::io::ErrorKind::TimedOut)??,
+            None => handshake.await?,
+        };
+        serve_connection(AsyncTransport::tls(stream), router, config, shutdown).await.map_err(std::io::Error::other)
+    });
+}
+
+/// Runs the connection on a runtime of its own, which keeps the worker until the connection is closed.
+fn block_on_connection<F: Future<Output = std::io::Result<()>>>(config: &ServerConfig, serve: impl FnOnce(Arc<ServerConfig>) -> F) {
+    let runtime = match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+        Ok(runtime) => runtime,
+        Err(_) => return,
+    };
+
+    if let Err(_e) = runtime.block_on(serve(Arc::new(config.clone()))) {
+        #[cfg(debug_assertions)]
+        println!("HTTP/2 connection failed: {_e}");
+    }
+}
+
+/// Serves an HTTP/2 connection on the current runtime.
+pub(crate) async fn serve_connection(
+    io: AsyncTransport,
+    router: Arc<SharedRouter>,
+    config: Arc<ServerConfig>,
+    shutdown: &ShutdownHandle,
+) -> Result<(), h2::Error> {
+    let mut connection = h2::server::Builder::new()
+        .max_header_list_size(config.limits.max_header_bytes.try_into().unwrap_or(u32::MAX))
+        .handshake(io)
+        .await?;
+
+    // Every stream holds a clone, so the connection is idle when this is the only one.
+    let active = Arc::new(());
+    let mut served = 0;
+    let mut closing = false;
+    let idle_timeout = sleep(config.keep_alive_timeout);
+    tokio::pin!(idle_timeout);
+
+    loop {
+        let accepted = tokio::select! {
+            accepted = connection.accept() => accepted,
+            _ = shutdown.wait(), if !closing => {
+                connection.graceful_shutdown();
+                closing = true;
+                continue;
+            }
+            _ = &mut idle_timeout, if !closing => {
+                if Arc::strong_count(&active) == 1 {
+                    connection.graceful_shutdown();
+                    closing = true;
+                } else {
+                    idle_timeout.set(sleep(config.keep_alive_timeout));
+                }
+                continue;
+            }
+        };
+
+        let (request, respond) = match accepted {
+            Some(accepted) => accepted?,
+            None => return Ok(()),
+        };
+        tokio::spawn(handle_stream(request, respond, router.clone(), config.clone(), active.clone()));
+
+        served += 1;
+        idle_timeout.set(sleep(config.keep_alive_timeout));
+        if !closing && config.max_requests_per_connection.is_some_and(|max| served >= max) {
+            connection.graceful_shutdown();
+            closing = true;
+        }
+    }
+}
+
+/// Serves a single request of the connection.
+async fn handle_stream(
+    request: http::Request<RecvStream>,
+    mut send: SendResponse<Bytes>,
+    router: Arc<SharedRouter>,
+    config: Arc<ServerConfig>,
+    _active: Arc<()>,
+) {
+    let (parts, mut body) = request.into_parts();
+    let head_only = parts.method == http::Method::HEAD;
+
+    let response = match read_head(&parts, &config.limits) {
+        Ok(mut request) => {
+            // Paths that are not canonical may be answered without routing them.
+            let normalized = normalize_request(&mut request, config.path_normalization);
+
+            // Get the path resolver from a snapshot of the router, so changing it doesn't wait for this request.
+            let snapshot = router.load();
+            let found = match normalized {
+                Some(_) => None,
+                None => snapshot.find(&request.path),
+            };
+
+            let limits = body_limits(found.as_ref(), &request, &config);
+            let resolved = found.map(|found| (found.resolver(), found.into_data()));
+
+            match with_timeout(limits.read_timeout, read_body(&mut body, &limits, &mut request.trailers)).await {
+                Ok(data) => {
+                    request.body = data;
+
+                    match normalized {
+                        Some(response) => response,
+                        None => respond_async(resolved, request).await,
+                    }
+                }
+                Err(e) => match e.status() {
+                    Some(status) => Response::new().with_status(status).with_body(e.to_string()),
+                    None => return,
+                },
+            }
+        }
+        Err(e) => match e.status() {
+            Some(status) => Response::new().with_status(status).with_body(e.to_string()),
+            None => return,
+        },
+    };
+
+    let write_timeout = config.limits.write_timeout;
+    let sent = with_timeout(write_timeout, async {
+        send_response(&mut send, response, head_only).await.map_err(|_| RequestParseError::ConnectionClosed)
+    });
+    if sent.await.is_err() {
+        send.send_reset(h2::Reason::INTERNAL_ERROR);
+    }
+}
+
+/// Converts the head of an HTTP/2 request into a `Request` without a body.
+fn read_head(parts: &http::request::Parts, limits: &RequestLimits) -> Result<Request, RequestParseError> {
+    let mut request = Request {
+        method: parts.method.as_str().parse().map_err(|_| RequestParseError::InvalidRequestLine)?,
+        version: "HTTP/2.0".to_string(),
+        ..Default::default()
+    };
+    request.set_target(parts.uri.path_and_query().map(|target| target.as_str()).unwrap_or("/"));
+
+    // The authority replaces the Host header of HTTP/1.
+    if let Some(authority) = parts.uri.authority() {
+        request.headers.insert("Host".to_string(), authority.to_string());
+    }
+
+    if parts.headers.len() > limits.max_header_count {
+        return Err(RequestParseError::TooManyHeaders);
+    }
+    for (name, value) in &parts.headers {
+        let value = value.to_str().map_err(|_| RequestParseError::InvalidHeader)?;
+        append_field(&mut request.headers, name, value);
+    }
+
+    Ok(request)
+}
+
+/// Adds the field to the map, repeated fields are joined into one value like HTTP/1 allows.
+/// HTTP/2 clients may split the cookie into several fields, which are joined with `; ` instead.
+fn append_field(fields: &mut HashMap<String, String>, name: &http::HeaderName, value: &str) {
+    let separator = match name == http::header::COOKIE {
+        true => "; ",
+        false => ", ",
+    };
+
+    fields
+        .entry(canonical_name(name.as_str()))
+        .and_modify(|joined| {
+            joined.push_str(separator);
+            joined.push_str(value);
+        })
+        .or_insert_with(|| value.to_string());
+}
+
+/// Capitalizes the lowercase HTTP/2 header name the way HTTP/1 clients usually send it,
+/// so middleware looking up `Content-Type` keeps working.
+fn canonical_name(name: &str) -> String {
+    name.split('-')
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Reads the whole body of the stream, trailer fields are added to `trailers`.
+async fn read_body(body: &mut RecvStream, limits: &RequestLimits, trailers: &mut HashMap<String, String>) -> Result<Vec<u8>, RequestParseError> {
+    let mut data = Vec::new();
+
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|_| RequestParseError::UnexpectedEof)?;
+        let _ = body.flow_control().release_capacity(chunk.len());
+
+        if data.len() + chunk.len() > limits.max_body_size {
+            return Err(RequestParseError::BodyTooLarge);
+        }
+        data.extend_from_slice(&chunk);
+    }
+
+    if let Some(fields) = body.trailers().await.map_err(|_| RequestParseError::UnexpectedEof)? {
+        for (name, value) in &fields {
+            let value = value.to_str().map_err(|_| RequestParseError::InvalidHeader)?;
+            append_field(trailers, name, value);
+        }
+    }
+
+    Ok(data)
+}
+
+/// Sends the response on the stream, streamed bodies are read on the blocking pool.
+/// Responses to HEAD requests end the stream after the headers.
+async fn send_response(send: &mut SendResponse<Bytes>, mut response: Response, head_only: bool) -> Result<(), h2::Error> {
+    let stream = response.take_stream().map(StreamingBody::into_reader);
+
+    let mut head = http::Response::builder().status(response.status.code());
+    for (name, value) in &response.headers {
+        if !CONNECTION_HEADERS.iter().any(|header| name.eq_ignore_ascii_case(header)) && !name.eq_ignore_ascii_case("content-length") {
+            head = head.header(name, value);
+        }
+    }
+    match &stream {
+        Some((_, Some(length))) => head = head.header("content-length", *length),
+        Some((_, None)) => {}
+        None => head = head.header("content-length", response.body.len()),
+    }
+    let head = match head.body(()) {
+        Ok(head) => head,
+        Err(_) => http::Response::builder().status(500).body(()).unwrap(),
+    };
+
+    match stream {
+        _ if head_only => {
+            send.send_response(head, true)?;
+        }
+        None if response.body.is_empty() => {
+            send.send_response(head, true)?;
+        }
+        None => {
+            let mut body = send.send_response(head, false)?;
+            body.send_data(Bytes::from(response.body), true)?;
+        }
+        Some((mut reader, _)) => {
+            let mut body = send.send_response(head, false)?;
+
+            loop {
+                let (returned, chunk) = match StreamingBody::read_chunk(reader).await {
+                    Ok(read) => read,
+                    Err(_) => {
+                        body.send_reset(h2::Reason::INTERNAL_ERROR);
+                        return Ok(());
+                    }
+                };
+                reader = returned;
+                if chunk.is_empty() {
+                    body.send_data(Bytes::new(), true)?;
+                    return Ok(());
+                }
+
+                // Wait for the client to accept more data before reading further.
+                body.reserve_capacity(chunk.len());
+                if let Some(capacity) = poll_fn(|cx| body.poll_capacity(cx)).await {
+                    capacity?;
+                }
+                body.send_data(Bytes::from(chunk), false)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{router::Method, server::{http_server::HttpServer, response::UnserializedBody}};
+
+    /// Sends a request over the HTTP/2 connection and returns the status with the body.
+    async fn get(client: &mut h2::client::SendRequest<Bytes>, path: &str) -> (u16, Vec<u8>) {
+        let request = http::Request::get(format!("http://localhost{path}")).body(()).unwrap();
+        let (response, _) = client.send_request(request, true).unwrap();
+        let response = response.await.unwrap();
+        let status = response.status().as_u16();
+
+        let mut body = response.into_body();
+        let mut data = Vec::new();
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk.unwrap();
+            let _ = body.flow_control().release_capacity(chunk.len());
+            data.extend_from_slice(&chunk);
+        }
+        (status, data)
+    }
+
+    fn server() -> HttpServer {
+        let mut server = HttpServer::new();
+        server
+            .add_route("/hello", Method::GET, || UnserializedBody(b"Hello!".to_vec()))
+            .add_route("/stream", Method::GET, || StreamingBody::from_chunks(vec!["Hello", ", World!"]));
+        server
+    }
+
+    #[test]
+    fn test_canonical_name() {
+        assert_eq!(canonical_name("content-type"), "Content-Type");
+        assert_eq!(canonical_name("x-request-id"), "X-Request-Id");
+        assert_eq!(canonical_name("accept"), "Accept");
+    }
+
+    #[test]
+    fn test_repeated_fields() {
+        let request = http::Request::get("http://localhost/")
+            .header("cookie", "a=1")
+            .header("cookie", "b=2")
+            .header("accept", "text/html")
+            .header("accept", "application/json")
+            .body(())
+            .unwrap();
+        let (parts, _) = request.into_parts();
+
+        let request = read_head(&parts, &RequestLimits::default()).unwrap();
+        assert_eq!(request.headers.get("Cookie").unwrap(), "a=1; b=2");
+        assert_eq!(request.headers.get("Accept").unwrap(), "text/html, application/json");
+    }
+
+    #[test]
+    fn test_h2c() {
+        let mut server = server();
+        let address = server.bind(("127.0.0.1", 0)).unwrap();
+        let _handle = server.spawn();
+
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(async {
+            let stream = tokio::net::TcpStream::connect(address).await.unwrap();
+            let (mut client, connection) = h2::client::handshake(stream).await.unwrap();
+            tokio::spawn(connection);
+
+            // Several requests over the same connection
+            assert_eq!(get(&mut client, "/hello").await, (200, b"Hello!".to_vec()));
+            assert_eq!(get(&mut client, "/stream").await, (200, b"Hello, World!".to_vec()));
+            assert_eq!(get(&mut client, "/missing").await.0, 404);
+        });
+    }
+
+    /// Sends the preface in two pieces, then an HTTP/1 request starting like it.
+    fn check_split_preface(address: std::net::SocketAddr) {
+        use std::{io::{Read, Write}, net::TcpStream, thread::sleep, time::Duration};
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.set_nodelay(true).unwrap();
+        stream.write_all(b"PRI * HT").unwrap();
+        sleep(Duration::from_millis(50));
+        stream.write_all(b"TP/2.0\r\n\r\nSM\r\n\r\n\0\0\0\x04\0\0\0\0\0").unwrap();
+
+        // The server answers with its SETTINGS frame.
+        let mut frame = [0; 9];
+        stream.read_exact(&mut frame).unwrap();
+        assert_eq!(frame[3], 0x04);
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.set_nodelay(true).unwrap();
+        stream.write_all(b"P").unwrap();
+        sleep(Duration::from_millis(50));
+        stream.write_all(b"UT /hello HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: 0\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+    }
+
+    #[test]
+    fn test_split_preface() {
+        let mut server = server();
+        let address = server.bind(("127.0.0.1", 0)).unwrap();
+        let _handle = server.spawn();
+
+        check_split_preface(address);
+    }
+
+    #[tokio::test]
+    async fn test_split_preface_async() {
+        let mut server = server();
+        let address = server.bind(("127.0.0.1", 0)).unwrap();
+        let shutdown = server.shutdown_handle();
+        let running = tokio::spawn(async move { server.run_async().await });
+
+        tokio::task::spawn_blocking(move || check_split_preface(address)).await.unwrap();
+
+        shutdown.shutdown();
+        running.await.unwrap();
+    }
+
+    /// Connects with TLS offering only h2 and checks that it was negotiated.
+    #[cfg(feature = "tls")]
+    async fn check_h2_alpn(address: std::net::SocketAddr, certificate: &[u8]) {
+        use tokio_rustls::{rustls::{pki_types::{CertificateDer, ServerName}, ClientConfig, RootCertStore}, TlsConnector};
+
+        let mut roots = RootCertStore::empty();
+        roots.add(CertificateDer::from(certificate.to_vec())).unwrap();
+        let mut config = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        config.alpn_protocols = vec![b"h2".to_vec()];
+
+        let stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        let server_name = ServerName::try_from("localhost").unwrap();
+        let stream = TlsConnector::from(Arc::new(config)).connect(server_name, stream).await.unwrap();
+        assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+
+        let (mut client, connection) = h2::client::handshake(stream).await.unwrap();
+        tokio::spawn(connection);
+
+        assert_eq!(get(&mut client, "/hello").await, (200, b"Hello!".to_vec()));
+        assert_eq!(get(&mut client, "/stream").await, (200, b"Hello, World!".to_vec()));
+    }
+
+    #[cfg(feature = "tls")]
+    fn tls_config() -> (crate::server::tls::TlsConfig, Vec<u8>) {
+        use crate::server::tls::{TlsCertificate, TlsConfig};
+
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let certificate = TlsCertificate::from_pem(generated.cert.pem().as_bytes(), generated.key_pair.serialize_pem().as_bytes()).unwrap();
+        (TlsConfig::new(certificate), generated.cert.der().to_vec())
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn test_h2_alpn() {
+        let (tls, certificate) = tls_config();
+        let mut server = server();
+        let address = server.bind_tls(("127.0.0.1", 0), tls).unwrap();
+        let _handle = server.spawn();
+
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(check_h2_alpn(address, &certificate));
+    }
+
+    #[cfg(feature = "tls")]
+    #[tokio::test]
+    async fn test_h2_alpn_async() {
+        let (tls, certificate) = tls_config();
+        let mut server = server();
+        let address = server.bind_tls(("127.0.0.1", 0), tls).unwrap();
+        let shutdown = server.shutdown_handle();
+        let running = tokio::spawn(async move { server.run_async().await });
+
+        check_h2_alpn(address, &certificate).await;
+
+        shutdown.shutdown();
+        running.await.unwrap();
+    }
+}
@@ -1,108 +1,572 @@
-use std::{net::TcpListener, sync::{Arc, RwLock}};
-
-use crate::{router::{router::{Router, Module}, Method}, utils::{thread_pool::ThreadPool}, server::{request::Request, response::Response}, pipeline::request_pipeline::IntoPipeline};
-
-pub type BindAddress<'a> = (&'a str, u16);
-
-/// Basic HTTP server implementation with support for TLS.
-pub struct HttpServer {
-    pub router: Arc<RwLock<Router>>,
-    pub(crate) thread_pool: ThreadPool,
-
-    #[doc(hidden)]
-    listener: Option<TcpListener>,
-}
-
-impl Default for HttpServer {
-    fn default() -> Self {
-        Self::new()
-    }
-}
-
-impl HttpServer {
-    /// Creates a new HTTP server.
-    /// The server will not start listening until `listen` is called.
-    /// The server will use the default thread pool size of 4.
-    pub fn new() -> Self {
-        Self {
-            router: Arc::new(RwLock::new(Router::new())),
-            thread_pool: ThreadPool::new(4),
-            listener: None,
-        }
-    }
-
-    pub fn dump_routes(&mut self) -> &mut Self {
-        println!("{:#?}", self.router.read().unwrap());
-        self
-    }
-
-    /// Gets the router for the server (this should not be used by the user).
-    pub fn get_router_write(&self) -> std::sync::RwLockWriteGuard<Router> {
-        self.router.write().unwrap()
-    }
-
-    /// Adds new routes to the server.
-    /// This is a convenience method for adding routes to the router.
-    pub fn add_route<T>(&mut self, path: &str, method: Method, controller: impl IntoPipeline<T>) -> &mut Self {
-        {
-            let mut router_write = self.router.write().unwrap();
-            router_write.add_route(path, method, controller);
-        }
-        self
-    }
-
-    /// Adds new module to the server.
-    pub fn add_module(&mut self, path: &str, module: impl Module) -> &mut Self {
-        {
-            let mut router_write = self.router.write().unwrap();
-            router_write.add_module(path, module);
-        }
-        self
-    }
-
-    /// Adds data to the global data container.
-    pub fn add_data<T: Send + Sync + 'static>(&mut self, value: T) -> &mut Self {
-        self.router.write().unwrap().data.add(value);
-        self
-    }
-
-    /// Starts listening for incoming connections on the specified address.
-    pub fn listen(&mut self, address: BindAddress) {
-        let listener = TcpListener::bind(address).unwrap();
-        self.listener = Some(listener);
-
-        #[cfg(debug_assertions)]
-        println!("Listening on {}:{}", address.0, address.1);
-
-        for stream in self.listener.as_ref().unwrap().incoming() {
-            match stream {
-                Ok(stream) => {
-                    let router = self.router.clone();
-                    self.thread_pool.queue(move || {
-                        println!("New connection: {}", stream.peer_addr().unwrap());
-
-                        // Parse the request.
-                        let request = Request::from_stream(stream);
-
-                        // Get the path resolver from the router.
-                        let router_read = router.read().unwrap();
-                        let path_resolver = router_read.resolve(&request.path);
-
-                        match path_resolver {
-                            Some((path_resolver, path_data)) => {
-                                path_resolver.resolve(&request, path_data).send_response(&request).unwrap();
-                            }
-                            None => {
-                                println!("No path resolver found for path: {}", request.path);
-                                Response::default().send_response(&request).unwrap()
-                            }
-                        }
-                    });
-                }
-                Err(e) => {
-                    println!("Error: {e}");
-                }
-            }
-        }
-    }
-}
\ No newline at end of file
+use std::{net::{SocketAddr, ToSocketAddrs}, sync::{Arc, Mutex}};
+
+use crate::{router::{error::RouteError, router::{Router, Module, SharedRouter}, Methods}, utils::{thread_pool::ThreadPool}, server::{config::ServerConfig, connection::handle_connection, handle::ServerHandle, idle::{self, IdleConnections}, transport::Transport, listener::{self, Listener}, shutdown::ShutdownHandle}, pipeline::{middleware::{IntoMiddleware, MiddlewareHandler}, request_pipeline::IntoPipeline}};
+#[cfg(feature = "tls")]
+use crate::server::tls::TlsConfig;
+#[cfg(feature = "tokio")]
+use crate::server::async_connection;
+
+pub type BindAddress<'a> = (&'a str, u16);
+
+/// Basic HTTP server implementation with support for TLS.
+pub struct HttpServer {
+    pub router: Arc<SharedRouter>,
+    pub(crate) thread_pool: ThreadPool,
+    pub(crate) config: Arc<ServerConfig>,
+    pub(crate) shutdown: ShutdownHandle,
+
+    #[doc(hidden)]
+    listeners: Vec<Listener>,
+}
+
+impl Default for HttpServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HttpServer {
+    /// Creates a new HTTP server.
+    /// The server will not start listening until `listen` or `run` is called.
+    /// The server uses the default `ServerConfig`, which can be changed with `with_config`.
+    pub fn new() -> Self {
+        let config = ServerConfig::default();
+
+        Self {
+            router: Arc::new(SharedRouter::default()),
+            thread_pool: ThreadPool::new(config.worker_count()),
+            config: Arc::new(config),
+            shutdown: ShutdownHandle::new(),
+            listeners: Vec::new(),
+        }
+    }
+
+    /// Sets the configuration of the server.
+    /// This should be called before `bind`, so the socket options are applied.
+    pub fn with_config(&mut self, config: ServerConfig) -> &mut Self {
+        if config.worker_count() != self.config.worker_count() {
+            self.thread_pool = ThreadPool::new(config.worker_count());
+        }
+        self.config = Arc::new(config);
+        self
+    }
+
+    /// Returns a handle that can be used to stop the server from another thread.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    pub fn dump_routes(&mut self) -> &mut Self {
+        println!("{:#?}", self.router.load());
+        self
+    }
+
+    /// Returns the current snapshot of the router.
+    pub fn router(&self) -> Arc<Router> {
+        self.router.load()
+    }
+
+    /// Replaces the router, this can be done while the server is running.
+    /// Requests in flight finish with the router they started with.
+    pub fn set_router(&mut self, router: Router) -> &mut Self {
+        self.router.store(router);
+        self
+    }
+
+    /// Changes the router, this can be done while the server is running.
+    /// Requests in flight finish with the router they started with.
+    pub fn update_router(&mut self, f: impl FnOnce(&mut Router)) -> &mut Self {
+        self.router.update(f);
+        self
+    }
+
+    /// Adds new routes to the server.
+    /// This is a convenience method for adding routes to the router.
+    /// The controller handles a method, several of them like `[Method::GET, Method::POST]` or `Methods::Any`.
+    pub fn add_route<T>(&mut self, path: &str, methods: impl Into<Methods>, controller: impl IntoPipeline<T>) -> &mut Self {
+        self.router.update(|router| {
+            router.add_route(path, methods, controller);
+        });
+        self
+    }
+
+    /// Adds new routes to the server, unless they conflict with the registered ones.
+    pub fn try_add_route<T>(&mut self, path: &str, methods: impl Into<Methods>, controller: impl IntoPipeline<T>) -> Result<&mut Self, RouteError> {
+        self.router.update(|router| router.try_add_route(path, methods, controller).map(|_| ()))?;
+        Ok(self)
+    }
+
+    /// Adds new module to the server.
+    pub fn add_module(&mut self, path: &str, module: impl Module) -> &mut Self {
+        self.router.update(|router| {
+            router.add_module(path, module);
+        });
+        self
+    }
+
+    /// Adds new module to the server, unless any of its routes conflicts with the registered ones.
+    pub fn try_add_module(&mut self, path: &str, module: impl Module) -> Result<&mut Self, RouteError> {
+        self.router.update(|router| router.try_add_module(path, module).map(|_| ()))?;
+        Ok(self)
+    }
+
+    /// Adds middleware to all routes of the server, see `Router::add_middleware`.
+    pub fn add_middleware<I, M: MiddlewareHandler + Send + Sync + 'static>(&mut self, middleware: impl IntoMiddleware<I, Middleware = M>) -> &mut Self {
+        self.router.update(|router| {
+            router.add_middleware(middleware);
+        });
+        self
+    }
+
+    /// Sets the controller answering requests that no route matches, see `Router::not_found`.
+    pub fn not_found<T>(&mut self, controller: impl IntoPipeline<T>) -> &mut Self {
+        self.router.update(|router| {
+            router.not_found(controller);
+        });
+        self
+    }
+
+    /// Mounts the controller on the prefix, it handles the prefix and every path below it.
+    /// The rest of the path is available as the `path` parameter.
+    pub fn mount<T>(&mut self, prefix: &str, methods: impl Into<Methods>, controller: impl IntoPipeline<T>) -> &mut Self {
+        self.router.update(|router| {
+            router.mount(prefix, methods, controller);
+        });
+        self
+    }
+
+    /// Mounts the module on the prefix, paths below it that the module has no route for go to its root route.
+    pub fn mount_module(&mut self, prefix: &str, module: impl Module) -> &mut Self {
+        self.router.update(|router| {
+            router.mount_module(prefix, module);
+        });
+        self
+    }
+
+    /// Adds data to the global data container.
+    pub fn add_data<T: Send + Sync + 'static>(&mut self, value: T) -> &mut Self {
+        self.router.update(|router| {
+            router.add_data(value);
+        });
+        self
+    }
+
+    /// Binds the server to the address, the server can be bound to several addresses at once.
+    /// Returns the bound address, which contains the actual port when binding to port 0.
+    /// Connections are not accepted until `run` is called.
+    pub fn bind(&mut self, address: impl ToSocketAddrs) -> std::io::Result<SocketAddr> {
+        let listener = Listener::new(listener::bind(address, &self.config)?);
+        self.add_listener(listener)
+    }
+
+    /// Binds the server to the address and serves HTTPS on it using the TLS config.
+    /// Plain and TLS addresses can be mixed on the same server.
+    #[cfg(feature = "tls")]
+    pub fn bind_tls(&mut self, address: impl ToSocketAddrs, tls: TlsConfig) -> std::io::Result<SocketAddr> {
+        let mut listener = Listener::new(listener::bind(address, &self.config)?);
+        listener.tls = Some(tls);
+        self.add_listener(listener)
+    }
+
+    fn add_listener(&mut self, listener: Listener) -> std::io::Result<SocketAddr> {
+        let local_addr = listener.listener.local_addr()?;
+
+        self.shutdown.add_wake_address(local_addr);
+        self.listeners.push(listener);
+
+        #[cfg(debug_assertions)]
+        println!("Listening on {local_addr}");
+
+        Ok(local_addr)
+    }
+
+    /// Returns the addresses the server is bound to.
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.listeners
+            .iter()
+            .filter_map(|listener| listener.listener.local_addr().ok())
+            .collect()
+    }
+
+    /// Starts listening for incoming connections on the specified address.
+    /// Blocks until the server is stopped using its `ShutdownHandle`.
+    pub fn listen(&mut self, address: BindAddress) {
+        self.bind(address).unwrap();
+        self.run();
+    }
+
+    /// Accepts connections on all bound addresses.
+    /// Blocks until the server is stopped using its `ShutdownHandle`, it can be bound and run again afterwards.
+    /// HTTP/2 connections hold a worker while they are open, see `ServerConfig::workers`.
+    pub fn run(&mut self) {
+        // The workers of a previous run are gone.
+        if self.thread_pool.is_shut_down() {
+            self.thread_pool = ThreadPool::new(self.config.worker_count());
+        }
+        self.router.compile();
+        let server = &*self;
+        let (idle, poller) = idle::idle_poller().expect("failed to create the idle connection poller");
+        std::thread::scope(|scope| {
+            for listener in &server.listeners {
+                let idle = idle.clone();
+                scope.spawn(move || server.accept(listener, &idle));
+            }
+
+            // Idle connections are served by a worker once they are readable.
+            let idle = idle.clone();
+            scope.spawn(move || poller.run(&server.shutdown, |connection| server.serve(connection.stream, connection.served, &idle)));
+        });
+
+        // Stop accepting connections and let the in-flight requests finish.
+        self.listeners.clear();
+
+        #[cfg(debug_assertions)]
+        println!("Shutting down");
+
+        self.thread_pool.shutdown(Some(self.config.shutdown_timeout));
+        self.shutdown.reset();
+    }
+
+    /// Runs the server on a background thread, it has to be bound to at least one address.
+    /// The returned handle can be used to get the bound addresses and to stop the server.
+    pub fn spawn(mut self) -> ServerHandle {
+        assert!(!self.listeners.is_empty(), "the server has to be bound before it is spawned");
+
+        let addresses = self.local_addrs();
+        let router = self.router.clone();
+        let shutdown = self.shutdown.clone();
+        let thread = std::thread::spawn(move || self.run());
+
+        ServerHandle {
+            addresses,
+            router,
+            shutdown,
+            thread: Some(thread),
+        }
+    }
+
+    /// Accepts connections on all bound addresses using the current tokio runtime.
+    /// Completes once the server is stopped using its `ShutdownHandle`.
+    /// Async controllers are awaited on the runtime and sync ones run on its blocking pool.
+    #[cfg(feature = "tokio")]
+    pub async fn run_async(&mut self) {
+        self.router.compile();
+        let mut accepting = tokio::task::JoinSet::new();
+        for listener in self.listeners.drain(..) {
+            accepting.spawn(accept_async(listener, self.router.clone(), self.config.clone(), self.shutdown.clone()));
+        }
+        while accepting.join_next().await.is_some() {}
+        self.shutdown.reset();
+
+        #[cfg(debug_assertions)]
+        println!("Shut down");
+    }
+
+    /// Accepts connections from the listener until the server shuts down.
+    fn accept(&self, listener: &Listener, idle: &IdleConnections) {
+        for stream in listener.listener.incoming() {
+            if self.shutdown.is_shutdown() {
+                break;
+            }
+
+            match stream {
+                Ok(stream) => {
+                    if let Err(e) = stream.set_nodelay(self.config.tcp_nodelay) {
+                        println!("Error: {e}");
+                        continue;
+                    }
+                    let stream = match listener.transport(stream) {
+                        Ok(stream) => stream,
+                        Err(e) => {
+                            println!("Error: {e}");
+                            continue;
+                        }
+                    };
+
+                    // The connection waits for its first request without holding a worker.
+                    if let Err(stream) = idle.park(Arc::new(Mutex::new(stream)), 0, self.config.keep_alive_timeout) {
+                        self.serve(stream, 0, idle);
+                    }
+                }
+                Err(e) => {
+                    println!("Error: {e}");
+                }
+            }
+        }
+    }
+
+    /// Serves the connection on a worker, `served` is the number of requests it already got answered.
+    fn serve(&self, stream: Arc<Mutex<Transport>>, served: usize, idle: &IdleConnections) {
+        let router = self.router.clone();
+        let config = self.config.clone();
+        let shutdown = self.shutdown.clone();
+        let idle = idle.clone();
+        self.thread_pool.queue(move || {
+            handle_connection(stream, served, router, &config, &shutdown, &idle);
+        });
+    }
+}
+
+/// Accepts connections from the listener until the server shuts down,
+/// then waits for the in-flight requests up to `ServerConfig::shutdown_timeout`.
+#[cfg(feature = "tokio")]
+async fn accept_async(listener: Listener, router: Arc<SharedRouter>, config: Arc<ServerConfig>, shutdown: ShutdownHandle) {
+    let incoming = listener.listener
+        .try_clone()
+        .and_then(|incoming| {
+            incoming.set_nonblocking(true)?;
+            tokio::net::TcpListener::from_std(incoming)
+        });
+    let incoming = match incoming {
+        Ok(incoming) => incoming,
+        Err(e) => {
+            println!("Error: {e}");
+            return;
+        }
+    };
+
+    let mut connections = tokio::task::JoinSet::new();
+    loop {
+        let accepted = tokio::select! {
+            biased;
+            _ = shutdown.wait() => break,
+            Some(_) = connections.join_next(), if !connections.is_empty() => continue,
+            accepted = incoming.accept() => accepted,
+        };
+
+        let handshake = accepted
+            .and_then(|(stream, _)| {
+                stream.set_nodelay(config.tcp_nodelay)?;
+                Ok(listener.transport_async(stream))
+            });
+        match handshake {
+            Ok(handshake) => {
+                let (router, config, shutdown) = (router.clone(), config.clone(), shutdown.clone());
+                connections.spawn(async move {
+                    // The TLS handshake gets as long as reading a request.
+                    let stream = match config.limits.read_timeout {
+                        Some(timeout) => tokio::time::timeout(timeout, handshake).await.unwrap_or_else(|_| Err(std::io::ErrorKind::TimedOut.into())),
+                        None => handshake.await,
+                    };
+                    match stream {
+                        Ok(stream) => async_connection::handle_connection(stream, router, config, shutdown).await,
+                        Err(_e) => {
+                            #[cfg(debug_assertions)]
+                            println!("Handshake failed: {_e}");
+                        }
+                    }
+                });
+            }
+            Err(e) => {
+                println!("Error: {e}");
+            }
+        }
+    }
+
+    // Stop accepting connections and let the in-flight requests finish.
+    drop(incoming);
+    drop(listener);
+    let finished = tokio::time::timeout(config.shutdown_timeout, async {
+        while connections.join_next().await.is_some() {}
+    });
+    if finished.await.is_err() {
+        connections.abort_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{pipeline::controller::ConfigurableController, router::Method, server::{config::RequestLimits, request::Request, response::UnserializedBody, test_client::{get, request, send}}};
+
+    #[test]
+    fn test_bind_reports_address() {
+        let mut server = HttpServer::new();
+        let v4 = server.bind(("127.0.0.1", 0)).unwrap();
+        let other = server.bind(("127.0.0.1", 0)).unwrap();
+
+        assert_ne!(v4.port(), 0);
+        assert_ne!(v4.port(), other.port());
+        assert_eq!(server.local_addrs(), vec![v4, other]);
+    }
+
+    #[test]
+    fn test_only_v6() {
+        use std::net::{Ipv4Addr, TcpStream};
+
+        // A dual-stack listener accepts IPv4 connections.
+        let mut server = HttpServer::new();
+        server.with_config(ServerConfig::new().with_only_v6(false));
+        let Ok(v6) = server.bind(("::", 0)) else {
+            // IPv6 is not available.
+            return;
+        };
+        assert!(TcpStream::connect((Ipv4Addr::LOCALHOST, v6.port())).is_ok());
+
+        // Otherwise IPv4 can be bound on the same port.
+        let mut server = HttpServer::new();
+        server.with_config(ServerConfig::new().with_only_v6(true));
+        let v6 = server.bind(("::", 0)).unwrap();
+        server.bind(("0.0.0.0", v6.port())).unwrap();
+    }
+
+    #[test]
+    fn test_run_again() {
+        let mut server = HttpServer::new();
+        server.add_route("/hello", Method::GET, || "Hello");
+        let shutdown = server.shutdown_handle();
+
+        for _ in 0..2 {
+            let address = server.bind(("127.0.0.1", 0)).unwrap();
+            std::thread::scope(|scope| {
+                let running = scope.spawn(|| server.run());
+
+                assert!(get(address, "/hello").starts_with("HTTP/1.1 200 OK\r\n"));
+
+                shutdown.shutdown();
+                running.join().unwrap();
+            });
+        }
+    }
+
+    #[test]
+    fn test_zero_workers() {
+        let mut server = HttpServer::new();
+        server
+            .with_config(ServerConfig::new().with_workers(0))
+            .add_route("/hello", Method::GET, || "Hello");
+        let address = server.bind(("127.0.0.1", 0)).unwrap();
+        let _handle = server.spawn();
+
+        assert!(get(address, "/hello").starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[test]
+    fn test_route_runs_concurrently() {
+        use std::{sync::Condvar, time::Duration};
+
+        // The requests share the route and wait for each other, so they only succeed when they run at the same time.
+        let arrived = Arc::new((Mutex::new(0), Condvar::new()));
+        let mut server = HttpServer::new();
+        server.add_route("/slow", Method::GET, move || {
+            let (count, all_arrived) = &*arrived;
+            let mut count = count.lock().unwrap();
+            *count += 1;
+            all_arrived.notify_all();
+            let (count, _) = all_arrived.wait_timeout_while(count, Duration::from_secs(5), |count| *count < 4).unwrap();
+            if *count == 4 { "Done" } else { "Alone" }.to_string()
+        });
+        let address = server.bind(("127.0.0.1", 0)).unwrap();
+        let _handle = server.spawn();
+
+        let clients = (0..4)
+            .map(|_| std::thread::spawn(move || get(address, "/slow")))
+            .collect::<Vec<_>>();
+
+        for client in clients {
+            assert!(client.join().unwrap().ends_with("\r\n\r\n\"Done\""));
+        }
+    }
+
+    #[test]
+    fn test_path_normalization() {
+        use crate::{router::PathParams, pipeline::controller::Data, server::config::PathNormalization};
+
+        let spawn = |policy| {
+            let mut server = HttpServer::new();
+            server
+                .with_config(ServerConfig::new().with_workers(1).with_path_normalization(policy))
+                .add_route("/users/:name", Method::GET, |params: Data<'_, PathParams>| params.get_param("name").unwrap())
+                .mount("/files", Method::GET, |params: Data<'_, PathParams>| params.get_param("path").unwrap());
+            let address = server.bind(("127.0.0.1", 0)).unwrap();
+            (address, server.spawn())
+        };
+
+        let (address, _handle) = spawn(PathNormalization::Lenient);
+        assert!(get(address, "/users/John%20Doe").ends_with("\r\n\r\n\"John Doe\""));
+        assert!(get(address, "//users/./%4aohn/").ends_with("\r\n\r\n\"John\""));
+        // Escaped slashes don't turn into segments that leave the mounted directory.
+        assert!(get(address, "/files/..%2F..%2Fetc%2Fpasswd").ends_with("\r\n\r\n\"..%2F..%2Fetc%2Fpasswd\""));
+
+        let (address, _handle) = spawn(PathNormalization::Redirect);
+        let response = get(address, "/users//John/?tab=posts");
+        assert!(response.starts_with("HTTP/1.1 308 Permanent Redirect\r\n"));
+        assert!(response.contains("Location: /users/John?tab=posts\r\n"));
+
+        let (address, _handle) = spawn(PathNormalization::Strict);
+        assert!(get(address, "/users/John/").starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(get(address, "/users/John").ends_with("\r\n\r\n\"John\""));
+    }
+
+    #[test]
+    fn test_head_and_options() {
+        let mut server = HttpServer::new();
+        server
+            .with_config(ServerConfig::new().with_workers(1))
+            .add_route("/users", Method::GET, || "users")
+            .add_route("/users", Method::POST, || "created");
+        let address = server.bind(("127.0.0.1", 0)).unwrap();
+        let _handle = server.spawn();
+
+        let response = request(address, "HEAD", "/users");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Length: 7\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+
+        let response = request(address, "OPTIONS", "/users");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Allow: GET, HEAD, OPTIONS, POST\r\n"));
+
+        let response = request(address, "DELETE", "/users");
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        assert!(response.contains("Allow: GET, HEAD, OPTIONS, POST\r\n"));
+
+        // The server answers for itself, and absolute-form targets are routed by their path
+        assert!(request(address, "OPTIONS", "*").starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(request(address, "GET", "http://localhost/users").ends_with("\r\n\r\n\"users\""));
+    }
+
+    /// Posts the body to the path on a new connection that is closed afterwards.
+    fn post(address: SocketAddr, path: &str, body: &str) -> String {
+        send(address, &format!("POST {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{body}", body.len()))
+    }
+
+    fn echo(request: &Request) -> UnserializedBody {
+        UnserializedBody(request.body.clone())
+    }
+
+    #[test]
+    fn test_route_limits() {
+        let mut server = HttpServer::new();
+        server
+            .with_config(ServerConfig::new().with_limits(RequestLimits::new().with_max_body_size(16)))
+            .add_route("/small", Method::POST, echo.with_limits(RequestLimits::new().with_max_body_size(4)))
+            .add_route("/echo", Method::POST, echo);
+        let address = server.bind(("127.0.0.1", 0)).unwrap();
+        let _handle = server.spawn();
+
+        // The route is stricter than the server, its neighbour isn't
+        assert!(post(address, "/small", "Hello").starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+        assert!(post(address, "/small", "Hi").ends_with("\r\n\r\nHi"));
+        assert!(post(address, "/echo", "Hello").ends_with("\r\n\r\nHello"));
+        assert!(post(address, "/echo", "Hello, World! Hello!").starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+    }
+
+    #[test]
+    fn test_router_limits() {
+        let mut server = HttpServer::new();
+        server
+            .update_router(|router| {
+                router.set_limits(RequestLimits::new().with_max_body_size(4));
+            })
+            .add_route("/large", Method::POST, echo.with_limits(RequestLimits::new().with_max_body_size(16)))
+            .add_route("/echo", Method::POST, echo);
+        let address = server.bind(("127.0.0.1", 0)).unwrap();
+        let _handle = server.spawn();
+
+        // The limits of the route override the ones of its router
+        assert!(post(address, "/large", "Hello").ends_with("\r\n\r\nHello"));
+        assert!(post(address, "/echo", "Hello").starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+        assert!(post(address, "/echo", "Hi").ends_with("\r\n\r\nHi"));
+    }
+}
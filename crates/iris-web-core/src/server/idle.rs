@@ -0,0 +1,144 @@
+use std::sync::{Arc, Mutex};
+#[cfg(unix)]
+use std::{io::{ErrorKind, Read, Write}, os::{fd::AsRawFd, unix::net::UnixStream}, sync::mpsc, time::{Duration, Instant}};
+
+use super::{shutdown::ShutdownHandle, transport::Transport};
+
+/// Connection waiting for its next request.
+pub(crate) struct IdleConnection {
+    pub(crate) stream: Arc<Mutex<Transport>>,
+    /// Number of requests already served over the connection.
+    pub(crate) served: usize,
+    #[cfg(unix)]
+    deadline: Option<Instant>,
+}
+
+/// Hands idle connections to the poller, so they don't hold a worker while waiting for a request.
+#[derive(Clone)]
+pub(crate) struct IdleConnections {
+    #[cfg(unix)]
+    sender: mpsc::Sender<IdleConnection>,
+    #[cfg(unix)]
+    waker: Arc<UnixStream>,
+}
+
+/// Waits for idle connections to become readable and passes them back to the workers.
+pub(crate) struct IdlePoller {
+    #[cfg(unix)]
+    receiver: mpsc::Receiver<IdleConnection>,
+    #[cfg(unix)]
+    wake: UnixStream,
+}
+
+/// Longest time the poller waits without checking whether the server is shutting down.
+#[cfg(unix)]
+const SHUTDOWN_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Creates the poller and the handle used to give it idle connections.
+#[cfg(unix)]
+pub(crate) fn idle_poller() -> std::io::Result<(IdleConnections, IdlePoller)> {
+    let (waker, wake) = UnixStream::pair()?;
+    waker.set_nonblocking(true)?;
+    wake.set_nonblocking(true)?;
+    let (sender, receiver) = mpsc::channel();
+
+    Ok((IdleConnections { sender, waker: Arc::new(waker) }, IdlePoller { receiver, wake }))
+}
+
+/// Creates the poller and the handle used to give it idle connections.
+/// Without `poll` idle connections stay with their worker.
+#[cfg(not(unix))]
+pub(crate) fn idle_poller() -> std::io::Result<(IdleConnections, IdlePoller)> {
+    Ok((IdleConnections {}, IdlePoller {}))
+}
+
+impl IdleConnections {
+    /// Gives the connection to the poller, it is returned when the poller is not running.
+    /// The connection is closed once it was idle for `timeout`.
+    #[cfg(unix)]
+    pub(crate) fn park(&self, stream: Arc<Mutex<Transport>>, served: usize, timeout: Option<Duration>) -> Result<(), Arc<Mutex<Transport>>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        self.sender
+            .send(IdleConnection { stream, served, deadline })
+            .map_err(|e| e.0.stream)?;
+        let _ = (&*self.waker).write(&[1]);
+        Ok(())
+    }
+
+    /// Gives the connection to the poller, it is returned when the poller is not running.
+    #[cfg(not(unix))]
+    pub(crate) fn park(&self, stream: Arc<Mutex<Transport>>, _served: usize, _timeout: Option<std::time::Duration>) -> Result<(), Arc<Mutex<Transport>>> {
+        Err(stream)
+    }
+}
+
+impl IdlePoller {
+    /// Passes readable connections to `ready` until the server shuts down.
+    #[cfg(unix)]
+    pub(crate) fn run(self, shutdown: &ShutdownHandle, mut ready: impl FnMut(IdleConnection)) {
+        let mut idle: Vec<IdleConnection> = Vec::new();
+        let mut fds: Vec<libc::pollfd> = Vec::new();
+
+        while !shutdown.is_shutdown() {
+            idle.extend(self.receiver.try_iter());
+
+            // Close the connections that were idle for too long.
+            let now = Instant::now();
+            idle.retain(|connection| {
+                let expired = connection.deadline.is_some_and(|deadline| deadline <= now);
+                if expired {
+                    connection.stream.lock().unwrap().close();
+                }
+                !expired
+            });
+
+            let timeout = idle
+                .iter()
+                .filter_map(|connection| connection.deadline)
+                .min()
+                .map_or(SHUTDOWN_CHECK_INTERVAL, |deadline| deadline.saturating_duration_since(now).min(SHUTDOWN_CHECK_INTERVAL));
+
+            fds.clear();
+            fds.push(libc::pollfd { fd: self.wake.as_raw_fd(), events: libc::POLLIN, revents: 0 });
+            fds.extend(idle.iter().map(|connection| libc::pollfd {
+                fd: connection.stream.lock().unwrap().tcp().as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            }));
+
+            // SAFETY: `fds` is a valid array of `fds.len()` pollfd structs and the descriptors outlive the call.
+            let polled = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout.as_millis().max(1) as libc::c_int) };
+            if polled < 0 {
+                if std::io::Error::last_os_error().kind() == ErrorKind::Interrupted {
+                    continue;
+                }
+                break;
+            }
+
+            if fds[0].revents != 0 {
+                let mut woken = [0; 64];
+                while matches!((&self.wake).read(&mut woken), Ok(read) if read > 0) {}
+            }
+
+            // Readable or closed connections go back to a worker, which finds out which one it is.
+            for index in (0..idle.len()).rev() {
+                if fds[index + 1].revents != 0 {
+                    ready(idle.swap_remove(index));
+                }
+            }
+        }
+
+        // New connections still get their first request served, the others are closed.
+        for connection in idle.into_iter().chain(self.receiver.try_iter()) {
+            if connection.served == 0 {
+                ready(connection);
+            } else {
+                connection.stream.lock().unwrap().close();
+            }
+        }
+    }
+
+    /// Without `poll` no connection is ever parked, so there is nothing to wait for.
+    #[cfg(not(unix))]
+    pub(crate) fn run(self, _shutdown: &ShutdownHandle, _ready: impl FnMut(IdleConnection)) {}
+}
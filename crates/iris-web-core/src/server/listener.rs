@@ -0,0 +1,85 @@
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+use super::{config::ServerConfig, transport::Transport};
+#[cfg(feature = "tls")]
+use super::tls::TlsConfig;
+#[cfg(feature = "tokio")]
+use super::async_io::AsyncTransport;
+
+/// Bound socket of the server, optionally terminating TLS.
+pub(crate) struct Listener {
+    pub(crate) listener: TcpListener,
+    #[cfg(feature = "tls")]
+    pub(crate) tls: Option<TlsConfig>,
+}
+
+impl Listener {
+    pub(crate) fn new(listener: TcpListener) -> Self {
+        Self {
+            listener,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+
+    /// Wraps the accepted connection for reading requests and writing responses.
+    pub(crate) fn transport(&self, stream: TcpStream) -> std::io::Result<Transport> {
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &self.tls {
+            return tls.accept(stream);
+        }
+
+        Ok(Transport::Plain(stream))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Listener {
+    /// Wraps a connection accepted by the async server, TLS connections finish their handshake first.
+    pub(crate) fn transport_async(&self, stream: tokio::net::TcpStream) -> impl std::future::Future<Output = std::io::Result<AsyncTransport>> + Send + 'static {
+        #[cfg(feature = "tls")]
+        let acceptor = self.tls.as_ref().map(TlsConfig::acceptor);
+
+        async move {
+            #[cfg(feature = "tls")]
+            if let Some(acceptor) = acceptor {
+                return Ok(AsyncTransport::tls(acceptor.accept(stream).await?));
+            }
+
+            Ok(AsyncTransport::plain(stream))
+        }
+    }
+}
+
+/// Creates a listening socket for the address using the socket options from the config.
+fn bind_address(address: SocketAddr, config: &ServerConfig) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(address), Type::STREAM, Some(Protocol::TCP))?;
+
+    if let Some(only_v6) = config.only_v6.filter(|_| address.is_ipv6()) {
+        socket.set_only_v6(only_v6)?;
+    }
+    socket.set_reuse_address(config.reuse_address)?;
+    #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos", target_os = "cygwin"))))]
+    socket.set_reuse_port(config.reuse_port)?;
+
+    socket.bind(&address.into())?;
+    socket.listen(config.backlog)?;
+
+    Ok(socket.into())
+}
+
+/// Binds the first of the addresses that can be bound, like `TcpListener::bind` does.
+pub(crate) fn bind(addresses: impl ToSocketAddrs, config: &ServerConfig) -> std::io::Result<TcpListener> {
+    let mut last_error = None;
+
+    for address in addresses.to_socket_addrs()? {
+        match bind_address(address, config) {
+            Ok(listener) => return Ok(listener),
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "could not resolve to any address")))
+}
@@ -1,3 +1,21 @@
-pub mod request;
-pub mod response;
-pub mod http_server;
\ No newline at end of file
+pub mod request;
+pub mod response;
+pub mod http_server;
+pub mod config;
+pub mod shutdown;
+pub mod handle;
+pub mod path;
+pub(crate) mod connection;
+pub(crate) mod idle;
+pub(crate) mod listener;
+pub(crate) mod transport;
+#[cfg(test)]
+pub(crate) mod test_client;
+#[cfg(feature = "tls")]
+pub mod tls;
+#[cfg(feature = "tokio")]
+pub(crate) mod async_io;
+#[cfg(feature = "tokio")]
+pub(crate) mod async_connection;
+#[cfg(feature = "http2")]
+pub(crate) mod http2;
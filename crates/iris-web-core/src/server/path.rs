@@ -0,0 +1,206 @@
+use std::borrow::Cow;
+
+use crate::router::Method;
+
+use super::{config::PathNormalization, request::Request, response::{Response, ResponseStatus}};
+
+/// Returns the canonical form of the path: empty and `.` segments are removed, `..` segments
+/// remove the one before them, escaped unreserved characters are decoded, other escapes use
+/// uppercase hex digits, and there is no trailing `/` except for the root path.
+///
+/// ```
+/// use iris_web_core::server::path::canonical_path;
+///
+/// assert_eq!(canonical_path("/a//b/./c/../%64/"), "/a/b/d");
+/// assert_eq!(canonical_path("/files/a%2fb"), "/files/a%2Fb");
+/// ```
+pub fn canonical_path(path: &str) -> Cow<'_, str> {
+    let mut segments: Vec<Cow<'_, str>> = Vec::new();
+    for segment in path.split('/') {
+        let segment = normalize_escapes(segment);
+        match &*segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            _ => segments.push(segment),
+        }
+    }
+
+    let canonical = format!("/{}", segments.join("/"));
+    if canonical == path {
+        Cow::Borrowed(path)
+    } else {
+        Cow::Owned(canonical)
+    }
+}
+
+fn hex(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|digit| digit as u8)
+}
+
+/// Returns the byte of the escape at the start of the input, like `%41`.
+fn escape(input: &[u8]) -> Option<u8> {
+    match input {
+        [b'%', high, low, ..] => Some(hex(*high)? << 4 | hex(*low)?),
+        _ => None,
+    }
+}
+
+fn is_unreserved(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~')
+}
+
+/// Decodes escaped unreserved characters, other escapes are kept with uppercase hex digits.
+fn normalize_escapes(segment: &str) -> Cow<'_, str> {
+    if !segment.contains('%') {
+        return Cow::Borrowed(segment);
+    }
+
+    let bytes = segment.as_bytes();
+    let mut normalized = String::with_capacity(segment.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match escape(&bytes[index..]) {
+            Some(byte) if is_unreserved(byte) => normalized.push(byte as char),
+            Some(byte) => normalized.push_str(&format!("%{byte:02X}")),
+            None => {
+                // Not an escape, the character is kept as it is.
+                let char = segment[index..].chars().next().unwrap();
+                normalized.push(char);
+                index += char.len_utf8();
+                continue;
+            }
+        }
+        index += 3;
+    }
+    Cow::Owned(normalized)
+}
+
+/// Decodes all escapes of a path segment, invalid UTF-8 is replaced.
+///
+/// ```
+/// use iris_web_core::server::path::percent_decode;
+///
+/// assert_eq!(percent_decode("John%20Doe"), "John Doe");
+/// assert_eq!(percent_decode("a%2Fb"), "a/b");
+/// ```
+pub fn percent_decode(segment: &str) -> Cow<'_, str> {
+    decode(segment, |_| true)
+}
+
+/// Decodes the escapes of several segments of a path, except `%2F` and `%25`.
+/// They stay escaped, so the decoded path has the same segments and can't reach outside of them.
+///
+/// ```
+/// use iris_web_core::server::path::percent_decode_path;
+///
+/// assert_eq!(percent_decode_path("a%20b/c"), "a b/c");
+/// assert_eq!(percent_decode_path("..%2F..%2Fetc%2Fpasswd"), "..%2F..%2Fetc%2Fpasswd");
+/// ```
+pub fn percent_decode_path(path: &str) -> Cow<'_, str> {
+    decode(path, |byte| !matches!(byte, b'/' | b'%'))
+}
+
+/// Decodes the escapes of the bytes that `decoded` accepts.
+fn decode(input: &str, decoded: fn(u8) -> bool) -> Cow<'_, str> {
+    if !input.contains('%') {
+        return Cow::Borrowed(input);
+    }
+
+    let bytes = input.as_bytes();
+    let mut output = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match escape(&bytes[index..]).filter(|byte| decoded(*byte)) {
+            Some(byte) => {
+                output.push(byte);
+                index += 3;
+            }
+            None => {
+                output.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    Cow::Owned(String::from_utf8_lossy(&output).into_owned())
+}
+
+/// Applies the policy to the path of the request before it is routed.
+/// Returns the response for a request that is not routed.
+pub(crate) fn normalize_request(request: &mut Request, policy: PathNormalization) -> Option<Response> {
+    // `OPTIONS *` asks about the server itself, not about one of its routes.
+    if request.path == "*" {
+        let status = match request.method {
+            Method::OPTIONS => ResponseStatus::Ok,
+            _ => ResponseStatus::BadRequest,
+        };
+        return Some(Response::new().with_status(status));
+    }
+
+    let canonical = match canonical_path(&request.path) {
+        Cow::Borrowed(_) => return None,
+        Cow::Owned(canonical) => canonical,
+    };
+
+    match policy {
+        PathNormalization::Strict => Some(Response::new().with_status(ResponseStatus::NotFound)),
+        PathNormalization::Redirect => {
+            let location = match request.query.is_empty() {
+                true => canonical,
+                false => format!("{canonical}?{}", request.query),
+            };
+            let mut response = Response::new().with_status(ResponseStatus::PermanentRedirect);
+            response.headers.insert("Location".to_string(), location);
+            Some(response)
+        }
+        PathNormalization::Lenient => {
+            request.path = canonical;
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_canonical_path() {
+        assert_eq!(canonical_path("/"), "/");
+        assert_eq!(canonical_path(""), "/");
+        assert_eq!(canonical_path("/a/b/"), "/a/b");
+        assert_eq!(canonical_path("/../a/%2e%2E/b"), "/b");
+        assert_eq!(canonical_path("/%7euser/%c3%a9"), "/~user/%C3%A9");
+        assert_eq!(canonical_path("/100%/%zz"), "/100%/%zz");
+        assert!(matches!(canonical_path("/a/b"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_policies() {
+        let mut request = Request::default();
+        request.set_target("/users//5/?tab=posts");
+
+        assert!(normalize_request(&mut request.clone(), PathNormalization::Strict).is_some_and(|response| response.status.code() == 404));
+
+        let response = normalize_request(&mut request.clone(), PathNormalization::Redirect).unwrap();
+        assert_eq!(response.status.code(), 308);
+        assert_eq!(response.headers["Location"], "/users/5?tab=posts");
+
+        assert!(normalize_request(&mut request, PathNormalization::Lenient).is_none());
+        assert_eq!(request.path, "/users/5");
+    }
+
+    proptest! {
+        #[test]
+        fn canonical_path_is_stable(path in "(/[a-z.%0-9A-F]{0,4}){0,6}/?") {
+            let canonical = canonical_path(&path).into_owned();
+            prop_assert!(canonical.starts_with('/'));
+            prop_assert!(!canonical.contains("//"));
+            prop_assert!(canonical == "/" || !canonical.ends_with('/'));
+            prop_assert!(canonical.split('/').all(|segment| segment != "." && segment != ".."));
+            prop_assert!(matches!(canonical_path(&canonical), Cow::Borrowed(_)));
+        }
+    }
+}
@@ -1,104 +1,882 @@
-use std::{collections::HashMap, sync::{Arc, Mutex}, net::TcpStream, io::{BufReader, BufRead, Read}};
-
-/// Struct representing a request to a server endpoint.
-/// This is used internally by Iris but can be used to inspect the request at lower levels.
-#[derive(Debug, Clone, Default)]
-pub struct Request {
-    pub method: String,
-    pub path: String,
-    pub version: String,
-    pub headers: HashMap<String, String>,
-    pub query_params: HashMap<String, String>,
-    pub body: Vec<u8>,
-
-    #[doc(hidden)]
-    pub(crate) stream: Option<Arc<Mutex<TcpStream>>>,
-}
-
-macro_rules! read_line {
-    ($buf:ident) => {{
-        let mut line = String::new();
-        $buf.read_line(&mut line).unwrap();
-        line
-    }};
-}
-
-impl Request {
-    #[doc(hidden)]
-    pub(crate) fn from_stream(stream: TcpStream) -> Self {
-        let mut request = Request::default();
-
-        let mut buf_reader = BufReader::new(&stream);
-
-        // Parse the first line
-        let first_line = read_line!(buf_reader);
-        let first_line_split: Vec<_> = first_line.split(' ').collect();
-        request.method = first_line_split[0].to_string();
-        request.path = first_line_split[1].to_string();
-        request.version = first_line_split[2].trim().to_string();
-
-        // Parse the query params
-        if request.path.contains('?') {
-            let path_split: Vec<_> = request.path.split('?').map(|s| s.to_string()).collect();
-            request.path = path_split[0].to_string();
-
-            let query_params = path_split[1].clone();
-            let query_params_split: Vec<_> = query_params.split('&').collect();
-            for query_param in query_params_split {
-                let query_param_split: Vec<_> = query_param.split('=').collect();
-                request.query_params.insert(
-                    query_param_split[0].to_string(),
-                    query_param_split[1].to_string(),
-                );
-            }
-        }
-
-        // Ensure no / at the end of the path
-        if request.path.ends_with('/') {
-            request.path.pop();
-        }
-
-        // Parse the headers
-        loop {
-            let line = read_line!(buf_reader);
-            if line.trim().is_empty() {
-                break;
-            }
-
-            let line_split: Vec<_> = line.split(':').collect();
-            request
-                .headers
-                .insert(line_split[0].to_string(), line_split[1].trim().to_string());
-        }
-
-        // Parse the body from the stream
-        let content_length = request
-            .headers
-            .get("Content-Length")
-            .unwrap_or(&"0".to_string())
-            .parse::<usize>()
-            .unwrap_or(0);
-
-        let mut body = vec![0; content_length];
-        buf_reader.read_exact(&mut body).unwrap();
-        request.body = body;
-
-        // Read the body from the buf_iter
-        #[cfg(debug_assertions)]
-        println!("{request:#?}");
-
-        request.stream = Some(Arc::new(Mutex::new(stream)));
-        request
-    }
-
-    /// Gets header value from the request by name and converts it to the specified type.
-    /// If the header is not found None is returned.
-    pub fn header<T: std::str::FromStr>(&self, name: &str) -> Option<T> {
-        self.headers
-            .get(name)
-            .map(|s| match s.parse::<T>() {
-                Ok(v) => v,
-                Err(_) => panic!("Failed to parse header value"),
-            })
-    }
-}
\ No newline at end of file
+use std::{collections::HashMap, sync::{Arc, Mutex}, io::{BufRead, ErrorKind, Read}};
+
+use crate::router::{is_token, Method};
+
+use super::{response::ResponseStatus, config::RequestLimits, transport::Transport};
+
+/// Struct representing a request to a server endpoint.
+/// This is used internally by Iris but can be used to inspect the request at lower levels.
+#[derive(Debug, Clone, Default)]
+pub struct Request {
+    pub method: Method,
+    pub path: String,
+    pub version: String,
+    pub headers: HashMap<String, String>,
+    pub query_params: HashMap<String, String>,
+    pub body: Vec<u8>,
+    /// The query string as it was sent, without the `?`.
+    pub(crate) query: String,
+    /// Trailer fields sent after a chunked body.
+    pub trailers: HashMap<String, String>,
+
+    #[doc(hidden)]
+    pub(crate) stream: Option<Arc<Mutex<Transport>>>,
+}
+
+/// Maximum size of a single chunk size line, including extensions.
+const MAX_CHUNK_LINE_SIZE: usize = 4 * 1024;
+
+/// Error returned when a request can't be read from a connection.
+#[derive(Debug)]
+pub enum RequestParseError {
+    /// The connection was closed before a new request started.
+    ConnectionClosed,
+    /// The client stopped sending data in the middle of a request.
+    UnexpectedEof,
+    /// Reading from the connection timed out.
+    /// `idle` is set when not a single byte of the request was received.
+    Timeout { idle: bool },
+    /// The request line is not `METHOD PATH VERSION`.
+    InvalidRequestLine,
+    /// A header line is not `Name: value`.
+    InvalidHeader,
+    /// The `Content-Length` header is not a valid number or there are several different ones.
+    InvalidContentLength,
+    /// Both `Content-Length` and `Transfer-Encoding` are present, which could be used to smuggle requests.
+    ConflictingFraming,
+    /// The `Transfer-Encoding` does not end with `chunked`, so the length of the body is unknown.
+    InvalidTransferEncoding,
+    /// The `Transfer-Encoding` uses codings other than `chunked`.
+    UnsupportedTransferEncoding,
+    /// The chunked body is malformed.
+    InvalidChunk,
+    /// The body is larger than the server accepts.
+    BodyTooLarge,
+    /// The request line is longer than the server accepts.
+    RequestLineTooLong,
+    /// The headers are larger than the server accepts.
+    HeadersTooLarge,
+    /// There are more headers than the server accepts.
+    TooManyHeaders,
+    /// Any other IO error.
+    Io(std::io::Error),
+}
+
+impl RequestParseError {
+    /// Returns the status that should be sent to the client,
+    /// or `None` if the connection should be closed without a response.
+    pub fn status(&self) -> Option<ResponseStatus> {
+        match self {
+            RequestParseError::ConnectionClosed
+            | RequestParseError::UnexpectedEof
+            | RequestParseError::Timeout { idle: true }
+            | RequestParseError::Io(_) => None,
+            RequestParseError::Timeout { idle: false } => Some(ResponseStatus::RequestTimeout),
+            RequestParseError::InvalidRequestLine
+            | RequestParseError::InvalidHeader
+            | RequestParseError::InvalidContentLength
+            | RequestParseError::ConflictingFraming
+            | RequestParseError::InvalidTransferEncoding
+            | RequestParseError::InvalidChunk => Some(ResponseStatus::BadRequest),
+            RequestParseError::UnsupportedTransferEncoding => Some(ResponseStatus::NotImplemented),
+            RequestParseError::RequestLineTooLong => Some(ResponseStatus::UriTooLong),
+            RequestParseError::HeadersTooLarge
+            | RequestParseError::TooManyHeaders => Some(ResponseStatus::RequestHeaderFieldsTooLarge),
+            RequestParseError::BodyTooLarge => Some(ResponseStatus::PayloadTooLarge),
+        }
+    }
+
+    fn from_io(error: std::io::Error, idle: bool) -> Self {
+        match error.kind() {
+            ErrorKind::WouldBlock | ErrorKind::TimedOut => RequestParseError::Timeout { idle },
+            ErrorKind::UnexpectedEof => RequestParseError::UnexpectedEof,
+            _ => RequestParseError::Io(error),
+        }
+    }
+}
+
+impl std::fmt::Display for RequestParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RequestParseError::ConnectionClosed => write!(f, "connection closed"),
+            RequestParseError::UnexpectedEof => write!(f, "connection closed in the middle of a request"),
+            RequestParseError::Timeout { .. } => write!(f, "timed out while reading the request"),
+            RequestParseError::InvalidRequestLine => write!(f, "invalid request line"),
+            RequestParseError::InvalidHeader => write!(f, "invalid header"),
+            RequestParseError::InvalidContentLength => write!(f, "invalid Content-Length header"),
+            RequestParseError::ConflictingFraming => write!(f, "both Content-Length and Transfer-Encoding headers are present"),
+            RequestParseError::InvalidTransferEncoding => write!(f, "invalid Transfer-Encoding header"),
+            RequestParseError::UnsupportedTransferEncoding => write!(f, "unsupported Transfer-Encoding"),
+            RequestParseError::InvalidChunk => write!(f, "invalid chunked body"),
+            RequestParseError::BodyTooLarge => write!(f, "request body is too large"),
+            RequestParseError::RequestLineTooLong => write!(f, "request line is too long"),
+            RequestParseError::HeadersTooLarge => write!(f, "request headers are too large"),
+            RequestParseError::TooManyHeaders => write!(f, "too many request headers"),
+            RequestParseError::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for RequestParseError {}
+
+/// Reads a single line without the line ending, counting it against the remaining `budget`.
+fn read_line(buf_reader: &mut impl BufRead, budget: &mut usize, idle: bool) -> Result<String, RequestParseError> {
+    if *budget == 0 {
+        return Err(RequestParseError::HeadersTooLarge);
+    }
+
+    let mut line = Vec::new();
+    let read = (&mut *buf_reader)
+        .take(*budget as u64)
+        .read_until(b'\n', &mut line)
+        .map_err(|e| RequestParseError::from_io(e, idle && line.is_empty()))?;
+
+    check_line(&line, read, budget, idle)?;
+    Ok(line_to_string(line))
+}
+
+/// Checks that a whole line was read within the `budget` and subtracts it.
+fn check_line(line: &[u8], read: usize, budget: &mut usize, idle: bool) -> Result<(), RequestParseError> {
+    if read == 0 && idle {
+        return Err(RequestParseError::ConnectionClosed);
+    }
+    if !line.ends_with(b"\n") {
+        if read == *budget {
+            return Err(RequestParseError::HeadersTooLarge);
+        }
+        return Err(RequestParseError::UnexpectedEof);
+    }
+    *budget -= read;
+    Ok(())
+}
+
+/// Removes the line ending and converts the line to a string.
+fn line_to_string(mut line: Vec<u8>) -> String {
+    line.pop();
+    if line.ends_with(b"\r") {
+        line.pop();
+    }
+
+    // Header values are usually ASCII, anything else is kept lossily.
+    String::from_utf8_lossy(&line).into_owned()
+}
+
+/// Splits a header line into its name and value.
+fn parse_header_line(line: &str) -> Result<(&str, &str), RequestParseError> {
+    let (name, value) = line.split_once(':').ok_or(RequestParseError::InvalidHeader)?;
+    // Folded lines start with whitespace, so they are rejected like other names that are not tokens.
+    if name.is_empty() || !name.bytes().all(is_token) {
+        return Err(RequestParseError::InvalidHeader);
+    }
+    Ok((name, value.trim()))
+}
+
+/// Splits an absolute-form target like `http://host/path` into its authority and the rest of it,
+/// which is empty or starts with `/` or `?`.
+fn split_absolute_form(target: &str) -> Option<(&str, &str)> {
+    let (scheme, rest) = target.split_once("://")?;
+    if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+        return None;
+    }
+
+    let (authority, rest) = rest.split_at(rest.find(['/', '?']).unwrap_or(rest.len()));
+    // User info is not part of the host.
+    let authority = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+    (!authority.is_empty()).then_some((authority, rest))
+}
+
+/// Reads a line of the chunked encoding, which must not be longer than `max`.
+fn read_chunk_line(buf_reader: &mut impl BufRead, max: usize) -> Result<String, RequestParseError> {
+    let mut budget = max;
+    read_line(buf_reader, &mut budget, false).map_err(|e| match e {
+        RequestParseError::HeadersTooLarge => RequestParseError::InvalidChunk,
+        e => e,
+    })
+}
+
+/// Decodes a body sent with `Transfer-Encoding: chunked`, trailer fields are added to `trailers`.
+fn read_chunked_body(
+    buf_reader: &mut impl BufRead,
+    limits: &RequestLimits,
+    trailers: &mut HashMap<String, String>,
+) -> Result<Vec<u8>, RequestParseError> {
+    let mut body = Vec::new();
+
+    loop {
+        // Chunk size line, optionally followed by extensions which are ignored
+        let size = parse_chunk_size(&read_chunk_line(buf_reader, MAX_CHUNK_LINE_SIZE)?)?;
+        if size == 0 {
+            break;
+        }
+        if body.len() as u64 + size > limits.max_body_size as u64 {
+            return Err(RequestParseError::BodyTooLarge);
+        }
+
+        // Chunk data followed by CRLF
+        let read = (&mut *buf_reader)
+            .take(size)
+            .read_to_end(&mut body)
+            .map_err(|e| RequestParseError::from_io(e, false))?;
+        if read as u64 != size {
+            return Err(RequestParseError::UnexpectedEof);
+        }
+        if !read_chunk_line(buf_reader, 2)?.is_empty() {
+            return Err(RequestParseError::InvalidChunk);
+        }
+    }
+
+    // Trailer section, limited like the headers
+    read_trailers(buf_reader, limits, trailers)?;
+
+    Ok(body)
+}
+
+/// Parses the size of a chunk from its size line.
+fn parse_chunk_size(line: &str) -> Result<u64, RequestParseError> {
+    let size = line.split(';').next().unwrap_or_default().trim_matches([' ', '\t']);
+    if size.is_empty() || size.len() > 16 || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(RequestParseError::InvalidChunk);
+    }
+    u64::from_str_radix(size, 16).map_err(|_| RequestParseError::InvalidChunk)
+}
+
+/// Reads the trailer section of a chunked body into `trailers`.
+fn read_trailers(buf_reader: &mut impl BufRead, limits: &RequestLimits, trailers: &mut HashMap<String, String>) -> Result<(), RequestParseError> {
+    let mut budget = limits.max_header_bytes;
+    loop {
+        let line = read_line(buf_reader, &mut budget, false)?;
+        if line.is_empty() {
+            break;
+        }
+        if trailers.len() >= limits.max_header_count {
+            return Err(RequestParseError::TooManyHeaders);
+        }
+
+        let (name, value) = parse_header_line(&line)?;
+        trailers.insert(name.to_string(), value.to_string());
+    }
+
+    Ok(())
+}
+
+/// Async counterparts of the readers above, used by the tokio server.
+#[cfg(feature = "tokio")]
+mod async_read {
+    use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+    use super::*;
+
+    /// Reads a single line including the line ending, counting it against the remaining `budget`.
+    pub(super) async fn read_raw_line(reader: &mut (impl AsyncBufRead + Unpin), budget: &mut usize, idle: bool) -> Result<Vec<u8>, RequestParseError> {
+        if *budget == 0 {
+            return Err(RequestParseError::HeadersTooLarge);
+        }
+
+        let mut line = Vec::new();
+        let read = (&mut *reader).take(*budget as u64).read_until(b'\n', &mut line).await;
+        let read = read.map_err(|e| RequestParseError::from_io(e, idle && line.is_empty()))?;
+
+        check_line(&line, read, budget, idle)?;
+        Ok(line)
+    }
+
+    /// Returns whether the line ends the head or the trailer section.
+    fn is_empty_line(line: &[u8]) -> bool {
+        line == b"\n" || line == b"\r\n"
+    }
+
+    /// Reads lines up to and including the empty one, so they can be parsed by the blocking parser.
+    pub(super) async fn read_section(reader: &mut (impl AsyncBufRead + Unpin), section: &mut Vec<u8>, budget: usize) -> Result<(), RequestParseError> {
+        let mut budget = budget;
+        loop {
+            let line = read_raw_line(reader, &mut budget, false).await?;
+            section.extend_from_slice(&line);
+            if is_empty_line(&line) {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Reads a line of the chunked encoding, which must not be longer than `max`.
+    async fn read_chunk_line(reader: &mut (impl AsyncBufRead + Unpin), max: usize) -> Result<String, RequestParseError> {
+        let mut budget = max;
+        match read_raw_line(reader, &mut budget, false).await {
+            Ok(line) => Ok(line_to_string(line)),
+            Err(RequestParseError::HeadersTooLarge) => Err(RequestParseError::InvalidChunk),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Decodes a body sent with `Transfer-Encoding: chunked`, trailer fields are added to `trailers`.
+    pub(super) async fn read_chunked_body(
+        reader: &mut (impl AsyncBufRead + Unpin),
+        limits: &RequestLimits,
+        trailers: &mut HashMap<String, String>,
+    ) -> Result<Vec<u8>, RequestParseError> {
+        let mut body = Vec::new();
+
+        loop {
+            let size = parse_chunk_size(&read_chunk_line(reader, MAX_CHUNK_LINE_SIZE).await?)?;
+            if size == 0 {
+                break;
+            }
+            if body.len() as u64 + size > limits.max_body_size as u64 {
+                return Err(RequestParseError::BodyTooLarge);
+            }
+
+            // Chunk data followed by CRLF
+            let read = (&mut *reader)
+                .take(size)
+                .read_to_end(&mut body)
+                .await
+                .map_err(|e| RequestParseError::from_io(e, false))?;
+            if read as u64 != size {
+                return Err(RequestParseError::UnexpectedEof);
+            }
+            if !read_chunk_line(reader, 2).await?.is_empty() {
+                return Err(RequestParseError::InvalidChunk);
+            }
+        }
+
+        let mut section = Vec::new();
+        read_section(reader, &mut section, limits.max_header_bytes).await?;
+        read_trailers(&mut &section[..], limits, trailers)?;
+
+        Ok(body)
+    }
+}
+
+/// How the body of a request is delimited, as announced by its headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyFraming {
+    /// The request has no body.
+    None,
+    /// The body has the given `Content-Length`.
+    ContentLength(usize),
+    /// The body uses `Transfer-Encoding: chunked`.
+    Chunked,
+}
+
+impl BodyFraming {
+    /// Returns whether a body follows the headers.
+    pub fn has_body(&self) -> bool {
+        !matches!(self, BodyFraming::None | BodyFraming::ContentLength(0))
+    }
+}
+
+impl Request {
+    /// Parses a single request from the reader.
+    /// The reader is left positioned right after the request body,
+    /// so pipelined requests can be parsed by calling this again.
+    ///
+    /// ```
+    /// use iris_web_core::server::request::Request;
+    ///
+    /// let raw = b"GET /hello?name=John HTTP/1.1\r\nHost: localhost\r\n\r\n";
+    /// let request = Request::parse(&mut &raw[..]).unwrap();
+    ///
+    /// assert_eq!(request.path, "/hello");
+    /// assert_eq!(request.query_params["name"], "John");
+    /// ```
+    pub fn parse(buf_reader: &mut impl BufRead) -> Result<Self, RequestParseError> {
+        Self::parse_with_limits(buf_reader, &RequestLimits::default())
+    }
+
+    /// Parses a single request from the reader, rejecting requests that exceed the limits.
+    pub fn parse_with_limits(buf_reader: &mut impl BufRead, limits: &RequestLimits) -> Result<Self, RequestParseError> {
+        let (mut request, framing) = Self::parse_head(buf_reader, limits)?;
+        request.read_body(buf_reader, framing, limits)?;
+        Ok(request)
+    }
+
+    /// Sets the path and query params from the request target.
+    pub(crate) fn set_target(&mut self, target: &str) {
+        let (path, query_params) = target.split_once('?').unwrap_or((target, ""));
+        for query_param in query_params.split('&').filter(|p| !p.is_empty()) {
+            // A parameter without a value is treated as a flag with an empty value.
+            let (key, value) = query_param.split_once('=').unwrap_or((query_param, ""));
+            self.query_params.insert(key.to_string(), value.to_string());
+        }
+
+        // The path is kept as it was sent, the server normalizes it before routing.
+        self.path = path.to_string();
+        self.query = query_params.to_string();
+    }
+
+    /// Parses the request line and headers, leaving the reader positioned at the start of the body.
+    /// The body can then be read with `read_body`, possibly using different limits.
+    pub fn parse_head(buf_reader: &mut impl BufRead, limits: &RequestLimits) -> Result<(Self, BodyFraming), RequestParseError> {
+        let mut request = Request::default();
+
+        // Parse the first line
+        let mut budget = limits.max_request_line;
+        let first_line = read_line(buf_reader, &mut budget, true).map_err(|e| match e {
+            RequestParseError::HeadersTooLarge => RequestParseError::RequestLineTooLong,
+            e => e,
+        })?;
+        let mut first_line_split = first_line.split(' ');
+        let mut authority = None;
+        match (first_line_split.next(), first_line_split.next(), first_line_split.next(), first_line_split.next()) {
+            (Some(method), Some(target), Some(version), None) if version.starts_with("HTTP/") => {
+                request.method = method.parse().map_err(|_| RequestParseError::InvalidRequestLine)?;
+                match target {
+                    _ if target.starts_with('/') => request.set_target(target),
+                    "*" if request.method == Method::OPTIONS => request.set_target(target),
+                    _ => {
+                        // Proxies send the absolute form, its authority replaces the Host header.
+                        let (host, target) = split_absolute_form(target).ok_or(RequestParseError::InvalidRequestLine)?;
+                        match target.starts_with('/') {
+                            true => request.set_target(target),
+                            false => request.set_target(&format!("/{target}")),
+                        }
+                        authority = Some(host);
+                    }
+                }
+                request.version = version.to_string();
+            }
+            _ => return Err(RequestParseError::InvalidRequestLine),
+        }
+
+        // Parse the headers, remembering the ones that decide how the body is framed
+        let mut budget = limits.max_header_bytes;
+        let mut count = 0;
+        let mut content_length: Option<String> = None;
+        let mut transfer_encoding: Vec<String> = Vec::new();
+        loop {
+            let line = read_line(buf_reader, &mut budget, false)?;
+            if line.is_empty() {
+                break;
+            }
+
+            count += 1;
+            if count > limits.max_header_count {
+                return Err(RequestParseError::TooManyHeaders);
+            }
+
+            let (name, value) = parse_header_line(&line)?;
+            if name.eq_ignore_ascii_case("Content-Length") {
+                if content_length.as_ref().is_some_and(|existing| existing != value) {
+                    return Err(RequestParseError::InvalidContentLength);
+                }
+                content_length = Some(value.to_string());
+            }
+            if name.eq_ignore_ascii_case("Transfer-Encoding") {
+                transfer_encoding.extend(
+                    value.split(',').map(|coding| coding.trim().to_ascii_lowercase()).filter(|coding| !coding.is_empty())
+                );
+            }
+            request.headers.insert(name.to_string(), value.to_string());
+        }
+
+        if let Some(authority) = authority {
+            request.headers.retain(|name, _| !name.eq_ignore_ascii_case("Host"));
+            request.headers.insert("Host".to_string(), authority.to_string());
+        }
+
+        // Decide how the body is framed
+        let framing = match (content_length, transfer_encoding.is_empty()) {
+            (Some(_), false) => return Err(RequestParseError::ConflictingFraming),
+            (None, false) => {
+                if transfer_encoding.last().map(String::as_str) != Some("chunked") {
+                    return Err(RequestParseError::InvalidTransferEncoding);
+                }
+                if transfer_encoding.len() > 1 {
+                    return Err(RequestParseError::UnsupportedTransferEncoding);
+                }
+                BodyFraming::Chunked
+            }
+            (Some(content_length), true) => {
+                if content_length.is_empty() || !content_length.bytes().all(|b| b.is_ascii_digit()) {
+                    return Err(RequestParseError::InvalidContentLength);
+                }
+                let content_length = content_length.parse::<usize>().map_err(|_| RequestParseError::InvalidContentLength)?;
+                BodyFraming::ContentLength(content_length)
+            }
+            (None, true) => BodyFraming::None,
+        };
+
+        Ok((request, framing))
+    }
+
+    /// Reads the body announced by the headers into the request.
+    pub fn read_body(&mut self, buf_reader: &mut impl BufRead, framing: BodyFraming, limits: &RequestLimits) -> Result<(), RequestParseError> {
+        self.body = match framing {
+            BodyFraming::None => Vec::new(),
+            BodyFraming::ContentLength(content_length) => {
+                if content_length > limits.max_body_size {
+                    return Err(RequestParseError::BodyTooLarge);
+                }
+
+                let mut body = vec![0; content_length];
+                buf_reader.read_exact(&mut body).map_err(|e| RequestParseError::from_io(e, false))?;
+                body
+            }
+            BodyFraming::Chunked => read_chunked_body(buf_reader, limits, &mut self.trailers)?,
+        };
+
+        #[cfg(debug_assertions)]
+        println!("{self:#?}");
+
+        Ok(())
+    }
+
+    /// Parses the request line and headers from an async reader, like `parse_head` does.
+    #[cfg(feature = "tokio")]
+    pub async fn parse_head_async(
+        reader: &mut (impl tokio::io::AsyncBufRead + Unpin),
+        limits: &RequestLimits,
+    ) -> Result<(Self, BodyFraming), RequestParseError> {
+        // Only the raw lines are read here, parsing them is left to the blocking parser.
+        let mut budget = limits.max_request_line;
+        let mut head = async_read::read_raw_line(reader, &mut budget, true).await.map_err(|e| match e {
+            RequestParseError::HeadersTooLarge => RequestParseError::RequestLineTooLong,
+            e => e,
+        })?;
+        async_read::read_section(reader, &mut head, limits.max_header_bytes).await?;
+
+        Self::parse_head(&mut &head[..], limits)
+    }
+
+    /// Reads the body announced by the headers from an async reader, like `read_body` does.
+    #[cfg(feature = "tokio")]
+    pub async fn read_body_async(
+        &mut self,
+        reader: &mut (impl tokio::io::AsyncBufRead + Unpin),
+        framing: BodyFraming,
+        limits: &RequestLimits,
+    ) -> Result<(), RequestParseError> {
+        use tokio::io::AsyncReadExt;
+
+        self.body = match framing {
+            BodyFraming::None => Vec::new(),
+            BodyFraming::ContentLength(content_length) => {
+                if content_length > limits.max_body_size {
+                    return Err(RequestParseError::BodyTooLarge);
+                }
+
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).await.map_err(|e| RequestParseError::from_io(e, false))?;
+                body
+            }
+            BodyFraming::Chunked => async_read::read_chunked_body(reader, limits, &mut self.trailers).await?,
+        };
+
+        Ok(())
+    }
+
+    /// Finds a header ignoring the case of its name.
+    pub(crate) fn find_header(&self, name: &str) -> Option<&String> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    /// Returns whether the client wants the connection to be kept open after this request.
+    /// HTTP/1.1 connections are persistent by default, HTTP/1.0 ones only with `Connection: keep-alive`.
+    pub fn keep_alive(&self) -> bool {
+        let connection = self.find_header("Connection").map(|value| value.to_ascii_lowercase());
+
+        let has_token = |token: &str| connection
+            .as_ref()
+            .map(|value| value.split(',').any(|t| t.trim() == token))
+            .unwrap_or(false);
+
+        if has_token("close") {
+            return false;
+        }
+
+        match self.version.as_str() {
+            "HTTP/1.0" => has_token("keep-alive"),
+            _ => true,
+        }
+    }
+
+    /// Gets header value from the request by name and converts it to the specified type.
+    /// If the header is not found None is returned.
+    pub fn header<T: std::str::FromStr>(&self, name: &str) -> Option<T> {
+        self.headers
+            .get(name)
+            .map(|s| match s.parse::<T>() {
+                Ok(v) => v,
+                Err(_) => panic!("Failed to parse header value"),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(version: &str, connection: Option<&str>) -> Request {
+        let mut request = Request {
+            version: version.to_string(),
+            ..Default::default()
+        };
+        if let Some(connection) = connection {
+            request.headers.insert("Connection".to_string(), connection.to_string());
+        }
+        request
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        assert!(request("HTTP/1.1", None).keep_alive());
+        assert!(request("HTTP/1.1", Some("keep-alive")).keep_alive());
+        assert!(!request("HTTP/1.1", Some("close")).keep_alive());
+        assert!(!request("HTTP/1.1", Some("Upgrade, Close")).keep_alive());
+
+        assert!(!request("HTTP/1.0", None).keep_alive());
+        assert!(request("HTTP/1.0", Some("Keep-Alive")).keep_alive());
+    }
+
+    fn parse(raw: &[u8]) -> Result<Request, RequestParseError> {
+        Request::parse(&mut &raw[..])
+    }
+
+    #[test]
+    fn test_parse() {
+        let request = parse(b"POST /hello/?name=John&flag HTTP/1.1\r\nHost: localhost:8080\r\nContent-Length: 4\r\n\r\nbody").unwrap();
+
+        assert_eq!(request.method, Method::POST);
+        // The path is normalized by the server before routing, not while parsing.
+        assert_eq!(request.path, "/hello/");
+        assert_eq!(request.query_params.get("name").unwrap(), "John");
+        assert_eq!(request.query_params.get("flag").unwrap(), "");
+        assert_eq!(request.headers.get("Host").unwrap(), "localhost:8080");
+        assert_eq!(request.body, b"body");
+    }
+
+    #[test]
+    fn test_parse_method() {
+        assert_eq!(parse(b"PROPFIND /files HTTP/1.1\r\n\r\n").unwrap().method, Method::Extension("PROPFIND".into()));
+        assert_eq!(parse(b"DELETE /files HTTP/1.1\r\n\r\n").unwrap().method, Method::DELETE);
+
+        // Methods are case-sensitive, the name decides about equality.
+        assert_eq!("get".parse::<Method>().unwrap().to_string(), "get");
+        assert_ne!("get".parse::<Method>().unwrap(), Method::GET);
+        assert_eq!(Method::Extension("GET".into()), Method::GET);
+        assert!("".parse::<Method>().is_err());
+    }
+
+    #[test]
+    fn test_parse_target_forms() {
+        // The absolute form is reduced to the path, its authority wins over the Host header.
+        let request = parse(b"GET http://example.com:8080/hello?name=John HTTP/1.1\r\nhost: other\r\n\r\n").unwrap();
+        assert_eq!(request.path, "/hello");
+        assert_eq!(request.query_params.get("name").unwrap(), "John");
+        assert_eq!(request.headers.get("Host").unwrap(), "example.com:8080");
+        assert!(!request.headers.contains_key("host"));
+
+        let request = parse(b"GET HTTPS://user@example.com?flag HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(request.path, "/");
+        assert_eq!(request.query, "flag");
+        assert_eq!(request.headers.get("Host").unwrap(), "example.com");
+
+        // The asterisk form is only used by OPTIONS.
+        assert_eq!(parse(b"OPTIONS * HTTP/1.1\r\n\r\n").unwrap().path, "*");
+        assert!(matches!(parse(b"GET * HTTP/1.1\r\n\r\n"), Err(RequestParseError::InvalidRequestLine)));
+        assert!(matches!(parse(b"GET ftp://example.com/ HTTP/1.1\r\n\r\n"), Err(RequestParseError::InvalidRequestLine)));
+        assert!(matches!(parse(b"GET http:///hello HTTP/1.1\r\n\r\n"), Err(RequestParseError::InvalidRequestLine)));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(matches!(parse(b""), Err(RequestParseError::ConnectionClosed)));
+        assert!(matches!(parse(b"GET /\r\n\r\n"), Err(RequestParseError::InvalidRequestLine)));
+        assert!(matches!(parse(b"GE(T / HTTP/1.1\r\n\r\n"), Err(RequestParseError::InvalidRequestLine)));
+        assert!(matches!(parse(b"GET / HTTP/1.1\r\nHost\r\n\r\n"), Err(RequestParseError::InvalidHeader)));
+        assert!(matches!(parse(b"GET / HTTP/1.1\r\nHost : localhost\r\n\r\n"), Err(RequestParseError::InvalidHeader)));
+        assert!(matches!(parse(b"GET / HTTP/1.1\r\nFoo Bar: 1\r\n\r\n"), Err(RequestParseError::InvalidHeader)));
+        assert!(matches!(parse(b"GET / HTTP/1.1\r\nX\x01: 1\r\n\r\n"), Err(RequestParseError::InvalidHeader)));
+        assert!(matches!(parse(b"GET / HTTP/1.1\r\nX-Long: a\r\n b\r\n\r\n"), Err(RequestParseError::InvalidHeader)));
+        assert!(matches!(parse(b"GET / HTTP/1.1\r\nX-Long: a\r\n\tb: c\r\n\r\n"), Err(RequestParseError::InvalidHeader)));
+        assert!(matches!(parse(b"GET / HTTP/1.1\r\nContent-Length: x\r\n\r\n"), Err(RequestParseError::InvalidContentLength)));
+        assert!(matches!(parse(b"GET / HTTP/1.1\r\nContent-Length: 10\r\n\r\nabc"), Err(RequestParseError::UnexpectedEof)));
+        assert!(matches!(parse(b"GET / HTTP/1.1\r\nHost: loc"), Err(RequestParseError::UnexpectedEof)));
+
+        let limits = RequestLimits::default();
+        let huge = format!("GET / HTTP/1.1\r\nX-Large: {}\r\n\r\n", "a".repeat(limits.max_header_bytes));
+        assert!(matches!(parse(huge.as_bytes()), Err(RequestParseError::HeadersTooLarge)));
+        let huge = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(limits.max_request_line));
+        assert!(matches!(parse(huge.as_bytes()), Err(RequestParseError::RequestLineTooLong)));
+        let many = format!("GET / HTTP/1.1\r\n{}\r\n", "X-Header: 1\r\n".repeat(limits.max_header_count + 1));
+        assert!(matches!(parse(many.as_bytes()), Err(RequestParseError::TooManyHeaders)));
+        let large = b"POST / HTTP/1.1\r\nContent-Length: 99999999999\r\n\r\n";
+        assert!(matches!(parse(large), Err(RequestParseError::BodyTooLarge)));
+    }
+
+    #[test]
+    fn test_parse_chunked() {
+        let request = parse(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5;ext=1\r\nHello\r\n8\r\n, World!\r\n0\r\nX-Checksum: 42\r\n\r\n").unwrap();
+        assert_eq!(request.body, b"Hello, World!");
+        assert_eq!(request.trailers.get("X-Checksum").unwrap(), "42");
+
+        assert!(matches!(
+            parse(b"POST / HTTP/1.1\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n"),
+            Err(RequestParseError::ConflictingFraming)
+        ));
+        assert!(matches!(
+            parse(b"POST / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 6\r\n\r\nHello!"),
+            Err(RequestParseError::InvalidContentLength)
+        ));
+        assert!(matches!(
+            parse(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked, gzip\r\n\r\n"),
+            Err(RequestParseError::InvalidTransferEncoding)
+        ));
+        assert!(matches!(
+            parse(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nxyz\r\n"),
+            Err(RequestParseError::InvalidChunk)
+        ));
+        assert!(matches!(
+            parse(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nHello!!\r\n0\r\n\r\n"),
+            Err(RequestParseError::InvalidChunk)
+        ));
+
+        let limits = RequestLimits::new().with_max_body_size(10);
+        let raw = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nHello\r\n8\r\n, World!\r\n0\r\n\r\n";
+        assert!(matches!(Request::parse_with_limits(&mut &raw[..], &limits), Err(RequestParseError::BodyTooLarge)));
+    }
+}
+
+#[cfg(test)]
+mod proptests {
+    use super::*;
+    use proptest::prelude::*;
+    use std::{collections::BTreeMap, io::BufReader};
+
+    /// Parts of a well-formed request.
+    #[derive(Debug, Clone)]
+    struct RawRequest {
+        method: String,
+        segments: Vec<String>,
+        query: BTreeMap<String, String>,
+        headers: BTreeMap<String, String>,
+        body: Vec<u8>,
+    }
+
+    impl RawRequest {
+        fn path(&self) -> String {
+            format!("/{}", self.segments.join("/"))
+        }
+
+        fn to_bytes(&self) -> Vec<u8> {
+            let mut target = self.path();
+            if !self.query.is_empty() {
+                let query: Vec<_> = self.query.iter().map(|(k, v)| format!("{k}={v}")).collect();
+                target = format!("{target}?{}", query.join("&"));
+            }
+
+            let mut raw = format!("{} {} HTTP/1.1\r\n", self.method, target);
+            for (name, value) in &self.headers {
+                raw.push_str(&format!("{name}: {value}\r\n"));
+            }
+            raw.push_str(&format!("Content-Length: {}\r\n\r\n", self.body.len()));
+
+            let mut raw = raw.into_bytes();
+            raw.extend_from_slice(&self.body);
+            raw
+        }
+
+        fn assert_matches(&self, request: &Request) {
+            assert_eq!(request.method.as_str(), self.method);
+            assert_eq!(request.path, self.path());
+            assert_eq!(request.version, "HTTP/1.1");
+            assert_eq!(request.query_params.len(), self.query.len());
+            for (key, value) in &self.query {
+                assert_eq!(&request.query_params[key], value);
+            }
+            for (name, value) in &self.headers {
+                assert_eq!(&request.headers[name], value);
+            }
+            assert_eq!(request.body, self.body);
+        }
+    }
+
+    fn raw_request() -> impl Strategy<Value = RawRequest> {
+        (
+            "[A-Z]{1,10}",
+            prop::collection::vec("[a-zA-Z0-9._~-]{1,8}", 0..5),
+            prop::collection::btree_map("[a-z]{1,8}", "[a-z0-9%+]{0,8}", 0..5),
+            prop::collection::btree_map("X-[A-Za-z0-9-]{1,12}", "[!-~]([ -~]{0,30}[!-~])?", 0..8),
+            prop::collection::vec(any::<u8>(), 0..256),
+        ).prop_map(|(method, segments, query, headers, body)| RawRequest { method, segments, query, headers, body })
+    }
+
+    proptest! {
+        #[test]
+        fn parse_never_panics(raw in prop::collection::vec(any::<u8>(), 0..1024)) {
+            let _ = Request::parse(&mut &raw[..]);
+        }
+
+        #[test]
+        fn parse_never_panics_on_near_valid_input(request in raw_request(), index in any::<prop::sample::Index>(), byte in any::<u8>()) {
+            let mut raw = request.to_bytes();
+            let index = index.index(raw.len());
+            raw[index] = byte;
+            let _ = Request::parse(&mut &raw[..]);
+        }
+
+        #[test]
+        fn parse_round_trips(request in raw_request()) {
+            let raw = request.to_bytes();
+            request.assert_matches(&Request::parse(&mut &raw[..]).unwrap());
+        }
+
+        #[test]
+        fn parse_does_not_depend_on_read_sizes(request in raw_request(), capacity in 1usize..64) {
+            let raw = request.to_bytes();
+            let mut reader = BufReader::with_capacity(capacity, &raw[..]);
+            request.assert_matches(&Request::parse(&mut reader).unwrap());
+        }
+
+        #[test]
+        fn parse_pipelined_requests(first in raw_request(), second in raw_request()) {
+            let mut raw = first.to_bytes();
+            raw.extend(second.to_bytes());
+
+            let mut reader = &raw[..];
+            first.assert_matches(&Request::parse(&mut reader).unwrap());
+            second.assert_matches(&Request::parse(&mut reader).unwrap());
+            prop_assert!(matches!(Request::parse(&mut reader), Err(RequestParseError::ConnectionClosed)));
+        }
+
+        #[test]
+        fn parse_chunked_round_trips(chunks in prop::collection::vec(prop::collection::vec(any::<u8>(), 1..64), 0..8)) {
+            let mut raw = b"POST /upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+            for chunk in &chunks {
+                raw.extend(format!("{:x}\r\n", chunk.len()).as_bytes());
+                raw.extend(chunk);
+                raw.extend(b"\r\n");
+            }
+            raw.extend(b"0\r\n\r\n");
+
+            let mut reader = BufReader::with_capacity(7, &raw[..]);
+            let request = Request::parse(&mut reader).unwrap();
+            prop_assert_eq!(request.body, chunks.concat());
+        }
+
+        #[test]
+        fn parse_rejects_invalid_header_names(request in raw_request(), name in "[A-Za-z-]{0,6}[\x00-\x09\x0b\x0c\x0e-\x20\"(),/;<=>?@\\[\\\\\\]{}\x7f][A-Za-z-]{0,6}") {
+            let mut raw = request.to_bytes();
+            let head = raw.windows(2).position(|window| window == b"\r\n").unwrap() + 2;
+            raw.splice(head..head, format!("{name}: value\r\n").into_bytes());
+            prop_assert!(matches!(Request::parse(&mut &raw[..]), Err(RequestParseError::InvalidHeader)));
+        }
+
+        #[test]
+        fn parse_rejects_folded_headers(request in raw_request(), fold in "[ \t][ -~]{0,20}") {
+            let mut raw = request.to_bytes();
+            let head = raw.windows(2).position(|window| window == b"\r\n").unwrap() + 2;
+            raw.splice(head..head, format!("X-Folded: value\r\n{fold}\r\n").into_bytes());
+            prop_assert!(matches!(Request::parse(&mut &raw[..]), Err(RequestParseError::InvalidHeader)));
+        }
+
+        #[test]
+        fn parse_rejects_truncated_requests(request in raw_request(), cut in any::<prop::sample::Index>()) {
+            let raw = request.to_bytes();
+            let cut = cut.index(raw.len());
+            prop_assert!(Request::parse(&mut &raw[..cut]).is_err());
+        }
+    }
+}
@@ -1,137 +1,496 @@
-use std::{collections::HashMap, io::Write};
-
-use super::request::Request;
-
-/// Struct that represents a response to a request.
-#[derive(Debug, Clone)]
-pub struct Response {
-    pub status: ResponseStatus,
-    pub headers: HashMap<String, String>,
-    pub body: Vec<u8>,
-}
-
-/// The status of a response.
-#[derive(Debug, Clone)]
-pub enum ResponseStatus {
-    Ok,
-    NotFound,
-    BadRequest,
-    InternalServerError,
-    MethodNotAllowed,
-    InvalidRequest,
-    Custom(String)
-}
-
-impl ResponseStatus {
-    /// Returns status code and reason phrase.
-    pub fn as_raw(&self) -> String {
-        match self {
-            ResponseStatus::Ok => "200 OK".to_string(),
-            ResponseStatus::NotFound => "404 Not Found".to_string(),
-            ResponseStatus::BadRequest => "400 Bad Request".to_string(),
-            ResponseStatus::InternalServerError => "500 Internal Server Error".to_string(),
-            ResponseStatus::MethodNotAllowed => "405 Method Not Allowed".to_string(),
-            ResponseStatus::InvalidRequest => "400 Bad Request".to_string(),
-            ResponseStatus::Custom(s) => s.to_string(),
-
-            #[allow(unreachable_patterns)] // For future proofing
-            _ => "500 Internal Server Error".to_string(),
-        }
-    }
-}
-
-impl Default for Response {
-    fn default() -> Self {
-        Self {
-            status: ResponseStatus::NotFound,
-            headers: HashMap::new(),
-            body: Vec::new(),
-        }
-    }
-}
-
-impl Response {
-    pub fn new() -> Self {
-        Self::default()
-    }
-
-    pub fn with_status(mut self, status: ResponseStatus) -> Self {
-        self.status = status;
-        self
-    }
-
-    pub fn with_body(mut self, body: impl IntoResponseBody) -> Self {
-        self.body = body.into_response_body();
-        self
-    }
-
-    #[doc(hidden)]
-    pub(crate) fn send_response(&mut self, request: &Request) -> std::io::Result<()> {
-        let mut response = String::new();
-
-        // Set the content length
-        self.headers.insert("Content-Length".to_string(), self.body.len().to_string());
-
-        // Add the status line
-        response.push_str(&format!("{} {}\r\n", request.version, self.status.as_raw()));
-
-        // Add the headers
-        for (key, value) in &self.headers {
-            response.push_str(&format!("{key}: {value}\r\n"));
-        }
-
-        // Add the body
-        response.push_str("\r\n");
-        response.push_str(&String::from_utf8_lossy(&self.body));
-
-        #[cfg(debug_assertions)]
-        println!("Response: {response}");
-
-        // Send the response
-        let mut stream = request.stream.as_ref().unwrap().lock().unwrap();
-        stream.write_all(response.as_bytes())?;
-        stream.flush()?;
-        stream.shutdown(std::net::Shutdown::Both)?;
-
-        Ok(())
-    }
-}
-
-pub trait IntoResponse {
-    fn into_response(self) -> Response;
-}
-
-impl IntoResponse for Response {
-    fn into_response(self) -> Response {
-        self
-    }
-}
-
-/// By default, Iris will serialize the body to JSON, but if you want to send a raw body, you can use this.
-pub struct UnserializedBody(pub Vec<u8>);
-
-impl IntoResponseBody for UnserializedBody {
-    fn into_response_body(self) -> Vec<u8> {
-        self.0
-    }
-}
-
-impl<T: IntoResponseBody> IntoResponse for T {
-    fn into_response(self) -> Response {
-        Response {
-            status: ResponseStatus::Ok,
-            headers: HashMap::new(),
-            body: self.into_response_body(),
-        }
-    }
-}
-
-pub trait IntoResponseBody {
-    fn into_response_body(self) -> Vec<u8>;
-}
-
-impl<T> IntoResponseBody for T where T: serde::Serialize {
-    fn into_response_body(self) -> Vec<u8> {
-        serde_json::to_vec(&self).unwrap()
-    }
-}
\ No newline at end of file
+use std::{collections::HashMap, io::{Read, Write}, sync::{Arc, Mutex}};
+
+use crate::router::Method;
+
+use super::request::Request;
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+/// Struct that represents a response to a request.
+/// Clones of a response with a `StreamingBody` share its reader, see there.
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: ResponseStatus,
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+    /// Body that is streamed to the client instead of `body`, set with `with_stream`.
+    stream: Option<StreamingBody>,
+}
+
+/// The status of a response.
+#[derive(Debug, Clone)]
+pub enum ResponseStatus {
+    Ok,
+    NotFound,
+    BadRequest,
+    InternalServerError,
+    MethodNotAllowed,
+    InvalidRequest,
+    RequestTimeout,
+    RequestHeaderFieldsTooLarge,
+    PayloadTooLarge,
+    UriTooLong,
+    NotImplemented,
+    PermanentRedirect,
+    Custom(String)
+}
+
+impl ResponseStatus {
+    /// Returns status code and reason phrase.
+    pub fn as_raw(&self) -> String {
+        match self {
+            ResponseStatus::Ok => "200 OK".to_string(),
+            ResponseStatus::NotFound => "404 Not Found".to_string(),
+            ResponseStatus::BadRequest => "400 Bad Request".to_string(),
+            ResponseStatus::InternalServerError => "500 Internal Server Error".to_string(),
+            ResponseStatus::MethodNotAllowed => "405 Method Not Allowed".to_string(),
+            ResponseStatus::InvalidRequest => "400 Bad Request".to_string(),
+            ResponseStatus::RequestTimeout => "408 Request Timeout".to_string(),
+            ResponseStatus::RequestHeaderFieldsTooLarge => "431 Request Header Fields Too Large".to_string(),
+            ResponseStatus::PayloadTooLarge => "413 Payload Too Large".to_string(),
+            ResponseStatus::UriTooLong => "414 URI Too Long".to_string(),
+            ResponseStatus::NotImplemented => "501 Not Implemented".to_string(),
+            ResponseStatus::PermanentRedirect => "308 Permanent Redirect".to_string(),
+            ResponseStatus::Custom(s) => s.to_string(),
+
+            #[allow(unreachable_patterns)] // For future proofing
+            _ => "500 Internal Server Error".to_string(),
+        }
+    }
+
+    /// Returns the numeric status code, custom statuses without a valid one are treated as 500.
+    pub fn code(&self) -> u16 {
+        self.as_raw()
+            .split(' ')
+            .next()
+            .and_then(|code| code.parse().ok())
+            .filter(|code| (100..1000).contains(code))
+            .unwrap_or(500)
+    }
+}
+
+impl Default for Response {
+    fn default() -> Self {
+        Self {
+            status: ResponseStatus::NotFound,
+            headers: HashMap::new(),
+            body: Vec::new(),
+            stream: None,
+        }
+    }
+}
+
+impl Response {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_status(mut self, status: ResponseStatus) -> Self {
+        self.status = status;
+        self
+    }
+
+    pub fn with_body(mut self, body: impl IntoResponseBody) -> Self {
+        self.body = body.into_response_body();
+        self
+    }
+
+    /// Streams the body to the client instead of sending a buffered one.
+    pub fn with_stream(mut self, stream: StreamingBody) -> Self {
+        self.stream = Some(stream);
+        self
+    }
+
+    /// Takes the streamed body out of the response.
+    #[cfg(feature = "http2")]
+    pub(crate) fn take_stream(&mut self) -> Option<StreamingBody> {
+        self.stream.take()
+    }
+
+    /// Returns whether the end of the body can only be signaled by closing the connection.
+    /// This is the case for streams of unknown length sent to HTTP/1.0 clients, which don't support chunked encoding.
+    #[doc(hidden)]
+    pub(crate) fn is_close_delimited(&self, version: &str) -> bool {
+        matches!(&self.stream, Some(stream) if stream.length.is_none()) && version == "HTTP/1.0"
+    }
+
+    #[doc(hidden)]
+    pub(crate) fn send_response(&mut self, request: &Request) -> std::io::Result<()> {
+        let mut stream = request.stream.as_ref().unwrap().lock().unwrap();
+        self.write_to(&request.version, request.method == Method::HEAD, &mut *stream)
+    }
+
+    /// Sets the framing headers and returns the status line with the headers,
+    /// together with whether the body has to be sent chunked.
+    fn head(&mut self, version: &str) -> (String, bool) {
+        let mut head = String::new();
+
+        // Set the framing headers
+        let chunked = match &self.stream {
+            Some(stream) => match stream.length {
+                Some(length) => {
+                    self.headers.insert("Content-Length".to_string(), length.to_string());
+                    false
+                }
+                None => {
+                    self.headers.remove("Content-Length");
+                    let chunked = version != "HTTP/1.0";
+                    if chunked {
+                        self.headers.insert("Transfer-Encoding".to_string(), "chunked".to_string());
+                    }
+                    chunked
+                }
+            },
+            None => {
+                self.headers.insert("Content-Length".to_string(), self.body.len().to_string());
+                false
+            }
+        };
+
+        // Add the status line
+        head.push_str(&format!("{} {}\r\n", version, self.status.as_raw()));
+
+        // Add the headers
+        for (key, value) in &self.headers {
+            head.push_str(&format!("{key}: {value}\r\n"));
+        }
+        head.push_str("\r\n");
+
+        #[cfg(debug_assertions)]
+        match &self.stream {
+            Some(_) => println!("Response: {head}<streamed body>"),
+            None => println!("Response: {head}<{} bytes of body>", self.body.len()),
+        }
+
+        (head, chunked)
+    }
+
+    /// Writes the response to the stream using the given HTTP version.
+    /// Responses to HEAD requests keep the headers of the body but leave it out.
+    #[doc(hidden)]
+    pub(crate) fn write_to(&mut self, version: &str, head_only: bool, stream: &mut impl Write) -> std::io::Result<()> {
+        let (head, chunked) = self.head(version);
+
+        // Send the response, the body is written as is to keep binary data intact
+        stream.write_all(head.as_bytes())?;
+        match self.stream.take() {
+            _ if head_only => {}
+            Some(body) if chunked => body.write_chunked(stream)?,
+            Some(body) => body.write_raw(stream)?,
+            None => stream.write_all(&self.body)?,
+        }
+        stream.flush()?;
+
+        Ok(())
+    }
+
+    /// Writes the response to the async stream, streamed bodies are read on the blocking pool.
+    #[cfg(feature = "tokio")]
+    #[doc(hidden)]
+    pub(crate) async fn write_to_async(&mut self, version: &str, head_only: bool, stream: &mut (impl AsyncWrite + Unpin)) -> std::io::Result<()> {
+        let (head, chunked) = self.head(version);
+
+        stream.write_all(head.as_bytes()).await?;
+        match self.stream.take() {
+            _ if head_only => {}
+            Some(body) => {
+                let (mut reader, length) = body.into_reader();
+                let mut written = 0;
+
+                loop {
+                    let (returned, chunk) = StreamingBody::read_chunk(reader).await?;
+                    reader = returned;
+                    if chunk.is_empty() {
+                        break;
+                    }
+
+                    written += chunk.len() as u64;
+                    if chunked {
+                        stream.write_all(format!("{:X}\r\n", chunk.len()).as_bytes()).await?;
+                        stream.write_all(&chunk).await?;
+                        stream.write_all(b"\r\n").await?;
+                    } else {
+                        stream.write_all(&chunk).await?;
+                    }
+                }
+
+                if chunked {
+                    stream.write_all(b"0\r\n\r\n").await?;
+                } else if length.is_some_and(|length| written != length) {
+                    return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "streaming body is shorter than its length"));
+                }
+            }
+            None => stream.write_all(&self.body).await?,
+        }
+        stream.flush().await?;
+
+        Ok(())
+    }
+}
+
+/// Body that is read and sent to the client piece by piece instead of being buffered in memory.
+/// When the length is unknown, it is sent using `Transfer-Encoding: chunked`.
+/// Clones share the reader, so the body is only sent once between them. A clone sent afterwards
+/// gets what is left of it, and with a length set sending it fails because the body is too short.
+#[derive(Clone)]
+pub struct StreamingBody {
+    reader: SharedReader,
+    length: Option<u64>,
+}
+
+/// Reader of a streaming body that can be shared between clones of a response.
+#[derive(Clone)]
+struct SharedReader(Arc<Mutex<Box<dyn Read + Send>>>);
+
+impl Read for SharedReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).read(buf)
+    }
+}
+
+impl std::fmt::Debug for StreamingBody {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StreamingBody")
+            .field("length", &self.length)
+            .finish()
+    }
+}
+
+impl StreamingBody {
+    /// Size of the chunks the body is read in.
+    const CHUNK_SIZE: usize = 8 * 1024;
+
+    /// Creates a body of unknown length streamed from the reader.
+    pub fn from_reader(reader: impl Read + Send + 'static) -> Self {
+        Self {
+            reader: SharedReader(Arc::new(Mutex::new(Box::new(reader)))),
+            length: None,
+        }
+    }
+
+    /// Creates a body of unknown length streamed from the chunks.
+    pub fn from_chunks<I>(chunks: I) -> Self
+    where
+        I: IntoIterator,
+        I::IntoIter: Send + 'static,
+        I::Item: Into<Vec<u8>>,
+    {
+        Self::from_reader(ChunkReader {
+            chunks: chunks.into_iter(),
+            current: Vec::new(),
+            position: 0,
+        })
+    }
+
+    /// Sets the exact length of the body so it is sent with `Content-Length` instead of chunked.
+    pub fn with_length(mut self, length: u64) -> Self {
+        self.length = Some(length);
+        self
+    }
+
+    /// Returns the reader of the body, limited to the declared length if there is one.
+    pub(crate) fn into_reader(self) -> (Box<dyn Read + Send>, Option<u64>) {
+        match self.length {
+            Some(length) => (Box::new(self.reader.take(length)), Some(length)),
+            None => (Box::new(self.reader), None),
+        }
+    }
+
+    /// Reads the next chunk of the body on the blocking pool, an empty chunk marks the end.
+    #[cfg(feature = "tokio")]
+    pub(crate) async fn read_chunk(mut reader: Box<dyn Read + Send>) -> std::io::Result<(Box<dyn Read + Send>, Vec<u8>)> {
+        let read = tokio::task::spawn_blocking(move || {
+            let mut chunk = vec![0; Self::CHUNK_SIZE];
+            let read = loop {
+                match reader.read(&mut chunk) {
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                    read => break read,
+                }
+            };
+            read.map(|read| {
+                chunk.truncate(read);
+                (reader, chunk)
+            })
+        });
+
+        read.await.map_err(std::io::Error::other)?
+    }
+
+    /// Writes the body as is, stopping at the declared length if there is one.
+    fn write_raw(self, stream: &mut impl Write) -> std::io::Result<()> {
+        let (mut reader, length) = self.into_reader();
+        let written = std::io::copy(&mut reader, stream)?;
+
+        if length.is_some_and(|length| written != length) {
+            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "streaming body is shorter than its length"));
+        }
+        Ok(())
+    }
+
+    /// Writes the body using the chunked transfer encoding.
+    fn write_chunked(mut self, stream: &mut impl Write) -> std::io::Result<()> {
+        let mut buffer = vec![0; Self::CHUNK_SIZE];
+
+        loop {
+            let read = match self.reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+
+            stream.write_all(format!("{read:X}\r\n").as_bytes())?;
+            stream.write_all(&buffer[..read])?;
+            stream.write_all(b"\r\n")?;
+        }
+
+        stream.write_all(b"0\r\n\r\n")
+    }
+}
+
+impl IntoResponse for StreamingBody {
+    fn into_response(self) -> Response {
+        Response::new()
+            .with_status(ResponseStatus::Ok)
+            .with_stream(self)
+    }
+}
+
+/// Adapter that reads an iterator of chunks as a continuous stream.
+struct ChunkReader<I> {
+    chunks: I,
+    current: Vec<u8>,
+    position: usize,
+}
+
+impl<I> Read for ChunkReader<I>
+where
+    I: Iterator,
+    I::Item: Into<Vec<u8>>,
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        // Move to the next non-empty chunk.
+        while self.position >= self.current.len() {
+            match self.chunks.next() {
+                Some(chunk) => {
+                    self.current = chunk.into();
+                    self.position = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+
+        let read = buf.len().min(self.current.len() - self.position);
+        buf[..read].copy_from_slice(&self.current[self.position..self.position + read]);
+        self.position += read;
+        Ok(read)
+    }
+}
+
+pub trait IntoResponse {
+    fn into_response(self) -> Response;
+}
+
+impl IntoResponse for Response {
+    fn into_response(self) -> Response {
+        self
+    }
+}
+
+/// By default, Iris will serialize the body to JSON, but if you want to send a raw body, you can use this.
+pub struct UnserializedBody(pub Vec<u8>);
+
+impl IntoResponseBody for UnserializedBody {
+    fn into_response_body(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+impl<T: IntoResponseBody> IntoResponse for T {
+    fn into_response(self) -> Response {
+        Response {
+            status: ResponseStatus::Ok,
+            headers: HashMap::new(),
+            body: self.into_response_body(),
+            stream: None,
+        }
+    }
+}
+
+pub trait IntoResponseBody {
+    fn into_response_body(self) -> Vec<u8>;
+}
+
+impl<T> IntoResponseBody for T where T: serde::Serialize {
+    fn into_response_body(self) -> Vec<u8> {
+        serde_json::to_vec(&self).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn unserialized_body_round_trips(body in prop::collection::vec(any::<u8>(), 0..4096)) {
+            let mut written = Vec::new();
+            UnserializedBody(body.clone()).into_response().write_to("HTTP/1.1", false, &mut written).unwrap();
+
+            let head_end = written.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+            let head = String::from_utf8(written[..head_end].to_vec()).unwrap();
+
+            let content_length = format!("Content-Length: {}\r\n", body.len());
+            prop_assert!(head.contains(&content_length));
+            prop_assert_eq!(&written[head_end..], &body[..]);
+        }
+    }
+
+    #[test]
+    fn test_streaming_body() {
+        let mut written = Vec::new();
+        StreamingBody::from_chunks(vec!["Hello", "", ", World!"])
+            .into_response()
+            .write_to("HTTP/1.1", false, &mut written)
+            .unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert!(written.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(written.ends_with("\r\n\r\n5\r\nHello\r\n8\r\n, World!\r\n0\r\n\r\n"));
+
+        let mut written = Vec::new();
+        StreamingBody::from_reader(&b"Hello, World!"[..])
+            .with_length(5)
+            .into_response()
+            .write_to("HTTP/1.1", false, &mut written)
+            .unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert!(written.contains("Content-Length: 5\r\n"));
+        assert!(written.ends_with("\r\n\r\nHello"));
+    }
+
+    #[test]
+    fn test_clone() {
+        let response = StreamingBody::from_chunks(vec!["Hello"]).into_response().with_status(ResponseStatus::NotFound);
+        let mut clone = response.clone();
+        assert_eq!(clone.status.code(), 404);
+
+        // The clones share the body, it is only sent once
+        let mut written = Vec::new();
+        clone.write_to("HTTP/1.1", false, &mut written).unwrap();
+        assert!(String::from_utf8(written).unwrap().ends_with("\r\n\r\n5\r\nHello\r\n0\r\n\r\n"));
+
+        let mut written = Vec::new();
+        response.clone().write_to("HTTP/1.1", false, &mut written).unwrap();
+        assert!(String::from_utf8(written).unwrap().ends_with("\r\n\r\n0\r\n\r\n"));
+
+        // With a length the later clone can't be sent, its body is shorter than that
+        let response = StreamingBody::from_reader(&b"Hello"[..]).with_length(5).into_response();
+        let mut written = Vec::new();
+        response.clone().write_to("HTTP/1.1", false, &mut written).unwrap();
+        assert!(String::from_utf8(written).unwrap().ends_with("\r\n\r\nHello"));
+
+        let sent = response.clone().write_to("HTTP/1.1", false, &mut Vec::new());
+        assert_eq!(sent.unwrap_err().kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,115 @@
+use std::{collections::HashMap, net::{SocketAddr, TcpStream, Shutdown, IpAddr, Ipv4Addr, Ipv6Addr}, sync::{Arc, Mutex, atomic::{AtomicBool, AtomicUsize, Ordering}}, time::Duration};
+
+/// Handle that can be used to stop a running server from another thread.
+/// Stopping the server closes the listeners, lets in-flight requests finish
+/// and waits for the workers up to `ServerConfig::shutdown_timeout`.
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownHandle {
+    state: Arc<ShutdownState>,
+}
+
+#[derive(Debug, Default)]
+struct ShutdownState {
+    requested: AtomicBool,
+    /// Addresses of the listeners, used to wake up blocked `accept` calls.
+    wake_addresses: Mutex<Vec<SocketAddr>>,
+    /// Connections that are currently waiting for a new request.
+    idle_connections: Mutex<HashMap<usize, TcpStream>>,
+    next_connection_id: AtomicUsize,
+    /// Wakes up async tasks waiting for the shutdown.
+    #[cfg(feature = "tokio")]
+    notify: tokio::sync::Notify,
+}
+
+impl ShutdownHandle {
+    /// Creates a new handle.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests the server to shut down. This does not wait for the server to stop.
+    pub fn shutdown(&self) {
+        if self.state.requested.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        // Wake up the listeners so they notice the request.
+        for address in self.state.wake_addresses.lock().unwrap().iter() {
+            let _ = TcpStream::connect_timeout(address, Duration::from_secs(1));
+        }
+
+        // Close connections that are not in the middle of a request.
+        for (_, stream) in self.state.idle_connections.lock().unwrap().drain() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+
+        #[cfg(feature = "tokio")]
+        self.state.notify.notify_waiters();
+    }
+
+    /// Waits until the shutdown is requested.
+    #[cfg(feature = "tokio")]
+    pub async fn wait(&self) {
+        let notified = self.state.notify.notified();
+        tokio::pin!(notified);
+
+        // Registered before checking the flag, so a shutdown in between is not missed.
+        notified.as_mut().enable();
+        if self.is_shutdown() {
+            return;
+        }
+        notified.await;
+    }
+
+    /// Returns whether the shutdown was requested.
+    pub fn is_shutdown(&self) -> bool {
+        self.state.requested.load(Ordering::SeqCst)
+    }
+
+    /// Shuts the server down when the process receives SIGINT or SIGTERM.
+    /// Only one signal handler can be installed per process.
+    #[cfg(feature = "signals")]
+    pub fn shutdown_on_signal(&self) -> Result<(), ctrlc::Error> {
+        let handle = self.clone();
+        ctrlc::set_handler(move || handle.shutdown())
+    }
+
+    /// Clears the request once the server stopped, so it can be run again.
+    #[doc(hidden)]
+    pub(crate) fn reset(&self) {
+        self.state.wake_addresses.lock().unwrap().clear();
+        self.state.requested.store(false, Ordering::SeqCst);
+    }
+
+    #[doc(hidden)]
+    pub(crate) fn add_wake_address(&self, address: SocketAddr) {
+        // Connecting to an unspecified address is not portable, use loopback instead.
+        let ip = match address.ip() {
+            IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            ip => ip,
+        };
+        self.state.wake_addresses.lock().unwrap().push(SocketAddr::new(ip, address.port()));
+    }
+
+    /// Marks the connection as idle so it can be closed on shutdown.
+    /// Returns `None` if the server is already shutting down.
+    #[doc(hidden)]
+    pub(crate) fn register_idle(&self, stream: &TcpStream) -> Option<usize> {
+        let id = self.state.next_connection_id.fetch_add(1, Ordering::SeqCst);
+        let mut idle_connections = self.state.idle_connections.lock().unwrap();
+
+        // Checked while holding the lock so the connection can't be missed by `shutdown`.
+        if self.is_shutdown() {
+            return None;
+        }
+
+        idle_connections.insert(id, stream.try_clone().ok()?);
+        Some(id)
+    }
+
+    #[doc(hidden)]
+    pub(crate) fn unregister_idle(&self, id: usize) {
+        self.state.idle_connections.lock().unwrap().remove(&id);
+    }
+}
@@ -0,0 +1,66 @@
+//! Raw HTTP/1.1 clients for the server tests.
+
+use std::{io::{Read, Write}, net::{SocketAddr, TcpStream}};
+
+/// Sends the raw request on a new connection and reads the response until the server closes it.
+pub(crate) fn send(address: SocketAddr, request: &str) -> String {
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+/// Sends a request without a body that closes the connection.
+pub(crate) fn request(address: SocketAddr, method: &str, target: &str) -> String {
+    send(address, &format!("{method} {target} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"))
+}
+
+/// Sends a GET request that closes the connection.
+pub(crate) fn get(address: SocketAddr, target: &str) -> String {
+    request(address, "GET", target)
+}
+
+/// Reads a single response with a `Content-Length` from a connection that is kept open.
+pub(crate) fn read_response(stream: &mut TcpStream) -> String {
+    let mut response = Vec::new();
+    let mut buf = [0; 1024];
+
+    loop {
+        if let Some(head_end) = response.windows(4).position(|window| window == b"\r\n\r\n") {
+            let head = String::from_utf8_lossy(&response[..head_end]).to_ascii_lowercase();
+            let length = head
+                .lines()
+                .find_map(|line| line.strip_prefix("content-length:"))
+                .map_or(0, |length| length.trim().parse().unwrap());
+            if response.len() >= head_end + 4 + length {
+                break;
+            }
+        }
+
+        let read = stream.read(&mut buf).unwrap();
+        if read == 0 {
+            break;
+        }
+        response.extend_from_slice(&buf[..read]);
+    }
+    String::from_utf8(response).unwrap()
+}
+
+/// Sends the raw request like `send`, using a tokio connection.
+#[cfg(feature = "tokio")]
+pub(crate) async fn send_async(address: SocketAddr, request: &str) -> String {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+/// Sends a GET request like `get`, using a tokio connection.
+#[cfg(feature = "tokio")]
+pub(crate) async fn get_async(address: SocketAddr, target: &str) -> String {
+    send_async(address, &format!("GET {target} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")).await
+}
@@ -0,0 +1,317 @@
+use std::{collections::HashMap, net::TcpStream, path::Path, sync::{Arc, RwLock}};
+
+use rustls::{crypto::CryptoProvider, pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer}, server::{ClientHello, ResolvesServerCert}, sign::CertifiedKey};
+
+use super::transport::Transport;
+
+/// Error returned when a certificate can't be loaded.
+#[derive(Debug)]
+pub enum TlsError {
+    /// The certificate or key file could not be read.
+    Io(std::io::Error),
+    /// The PEM data is malformed or doesn't contain the expected item.
+    Pem(rustls::pki_types::pem::Error),
+    /// The certificate chain is empty.
+    NoCertificates,
+    /// The private key is not supported or doesn't match the certificate.
+    Rustls(rustls::Error),
+}
+
+impl std::fmt::Display for TlsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TlsError::Io(e) => write!(f, "failed to read certificate: {e}"),
+            TlsError::Pem(e) => write!(f, "invalid PEM data: {e}"),
+            TlsError::NoCertificates => write!(f, "certificate chain is empty"),
+            TlsError::Rustls(e) => write!(f, "invalid certificate: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for TlsError {}
+
+impl From<std::io::Error> for TlsError {
+    fn from(e: std::io::Error) -> Self {
+        TlsError::Io(e)
+    }
+}
+
+impl From<rustls::pki_types::pem::Error> for TlsError {
+    fn from(e: rustls::pki_types::pem::Error) -> Self {
+        TlsError::Pem(e)
+    }
+}
+
+impl From<rustls::Error> for TlsError {
+    fn from(e: rustls::Error) -> Self {
+        TlsError::Rustls(e)
+    }
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+/// Certificate chain together with its private key.
+#[derive(Debug, Clone)]
+pub struct TlsCertificate {
+    key: Arc<CertifiedKey>,
+}
+
+impl TlsCertificate {
+    /// Loads the certificate from DER encoded certificates, starting with the end-entity one, and a private key.
+    pub fn from_der(chain: Vec<Vec<u8>>, key: Vec<u8>) -> Result<Self, TlsError> {
+        if chain.is_empty() {
+            return Err(TlsError::NoCertificates);
+        }
+
+        let chain = chain.into_iter().map(CertificateDer::from).collect();
+        let key = PrivateKeyDer::try_from(key).map_err(|e| rustls::Error::General(e.to_string()))?;
+        let key = CertifiedKey::from_der(chain, key, &provider())?;
+
+        Ok(Self { key: Arc::new(key) })
+    }
+
+    /// Loads the certificate from PEM encoded certificate chain and private key.
+    pub fn from_pem(chain: &[u8], key: &[u8]) -> Result<Self, TlsError> {
+        let chain = CertificateDer::pem_slice_iter(chain)
+            .map(|cert| cert.map(|cert| cert.to_vec()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let key = PrivateKeyDer::from_pem_slice(key)?;
+
+        Self::from_der(chain, key.secret_der().to_vec())
+    }
+
+    /// Loads the certificate from PEM files containing the certificate chain and private key.
+    pub fn from_pem_files(chain: impl AsRef<Path>, key: impl AsRef<Path>) -> Result<Self, TlsError> {
+        Self::from_pem(&std::fs::read(chain)?, &std::fs::read(key)?)
+    }
+}
+
+/// Picks the certificate by the server name the client sent (SNI).
+#[derive(Debug, Default)]
+struct CertificateResolver {
+    default: RwLock<Option<Arc<CertifiedKey>>>,
+    by_name: RwLock<HashMap<String, Arc<CertifiedKey>>>,
+}
+
+impl ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let by_name = client_hello
+            .server_name()
+            .and_then(|name| self.by_name.read().unwrap().get(&name.to_ascii_lowercase()).cloned());
+
+        by_name.or_else(|| self.default.read().unwrap().clone())
+    }
+}
+
+/// TLS configuration of a listener.
+///
+/// Clones share the certificates, so replacing a certificate on any of them
+/// affects every listener using the config. Connections that are already
+/// established keep the certificate they were started with.
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    resolver: Arc<CertificateResolver>,
+    server_config: Arc<rustls::ServerConfig>,
+}
+
+impl TlsConfig {
+    /// Creates a config serving the certificate to all clients without a more specific one.
+    pub fn new(certificate: TlsCertificate) -> Self {
+        let resolver = Arc::new(CertificateResolver::default());
+        *resolver.default.write().unwrap() = Some(certificate.key);
+
+        let mut server_config = rustls::ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .expect("the default provider supports the default protocol versions")
+            .with_no_client_auth()
+            .with_cert_resolver(resolver.clone());
+        server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        #[cfg(feature = "http2")]
+        server_config.alpn_protocols.insert(0, b"h2".to_vec());
+
+        Self {
+            resolver,
+            server_config: Arc::new(server_config),
+        }
+    }
+
+    /// Creates a config serving the certificate loaded from PEM files.
+    pub fn from_pem_files(chain: impl AsRef<Path>, key: impl AsRef<Path>) -> Result<Self, TlsError> {
+        Ok(Self::new(TlsCertificate::from_pem_files(chain, key)?))
+    }
+
+    /// Adds a certificate served to clients asking for the server name.
+    pub fn with_certificate(self, server_name: &str, certificate: TlsCertificate) -> Self {
+        self.set_certificate(server_name, certificate);
+        self
+    }
+
+    /// Replaces the default certificate, new connections use it right away.
+    pub fn set_default_certificate(&self, certificate: TlsCertificate) {
+        *self.resolver.default.write().unwrap() = Some(certificate.key);
+    }
+
+    /// Adds or replaces the certificate of the server name, new connections use it right away.
+    pub fn set_certificate(&self, server_name: &str, certificate: TlsCertificate) {
+        self.resolver.by_name.write().unwrap().insert(server_name.to_ascii_lowercase(), certificate.key);
+    }
+
+    /// Removes the certificate of the server name, its clients get the default one afterwards.
+    pub fn remove_certificate(&self, server_name: &str) {
+        self.resolver.by_name.write().unwrap().remove(&server_name.to_ascii_lowercase());
+    }
+
+    /// Wraps the accepted connection, the handshake happens on the first read.
+    #[doc(hidden)]
+    #[cfg(not(feature = "http2"))]
+    pub(crate) fn accept(&self, stream: TcpStream) -> std::io::Result<Transport> {
+        let connection = rustls::ServerConnection::new(self.server_config.clone()).map_err(std::io::Error::other)?;
+        Ok(Transport::Tls(Box::new(rustls::StreamOwned::new(connection, stream))))
+    }
+
+    /// Wraps the accepted connection, the protocol chosen with ALPN decides who does the handshake.
+    #[doc(hidden)]
+    #[cfg(feature = "http2")]
+    pub(crate) fn accept(&self, stream: TcpStream) -> std::io::Result<Transport> {
+        Ok(Transport::TlsPending(stream, self.server_config.clone()))
+    }
+
+    /// Returns the acceptor doing the handshake of connections accepted by the async server.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn acceptor(&self) -> tokio_rustls::TlsAcceptor {
+        self.server_config.clone().into()
+    }
+}
+
+/// Reads the ClientHello of a new connection from the blocking socket.
+#[cfg(feature = "http2")]
+pub(crate) fn read_client_hello(stream: &mut TcpStream) -> std::io::Result<rustls::server::Accepted> {
+    let mut acceptor = rustls::server::Acceptor::default();
+    loop {
+        if acceptor.read_tls(stream)? == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        match acceptor.accept() {
+            Ok(Some(accepted)) => return Ok(accepted),
+            Ok(None) => {}
+            Err((e, mut alert)) => {
+                let _ = alert.write_all(stream);
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e));
+            }
+        }
+    }
+}
+
+/// Returns whether the server picks h2 from the protocols offered by the client,
+/// the first protocol of the server the client supports is chosen, like rustls does.
+#[cfg(feature = "http2")]
+pub(crate) fn chooses_http2(accepted: &rustls::server::Accepted, config: &rustls::ServerConfig) -> bool {
+    let offered = accepted.client_hello().alpn().map(|offered| offered.collect::<Vec<_>>()).unwrap_or_default();
+    config.alpn_protocols
+        .iter()
+        .find(|protocol| offered.contains(&protocol.as_slice()))
+        .is_some_and(|protocol| protocol == b"h2")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use rustls::{pki_types::ServerName, ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+    use super::*;
+    use crate::{router::Method, server::{http_server::HttpServer, response::UnserializedBody}};
+
+    fn self_signed(name: &str) -> (TlsCertificate, Vec<u8>) {
+        let generated = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+        let certificate = TlsCertificate::from_pem(
+            generated.cert.pem().as_bytes(),
+            generated.key_pair.serialize_pem().as_bytes(),
+        ).unwrap();
+        (certificate, generated.cert.der().to_vec())
+    }
+
+    /// Sends a request to the server and returns the response with the certificate the server presented.
+    fn get(address: std::net::SocketAddr, server_name: &str, trusted: &[&[u8]]) -> (String, Vec<u8>) {
+        let mut roots = RootCertStore::empty();
+        for cert in trusted {
+            roots.add(CertificateDer::from(cert.to_vec())).unwrap();
+        }
+        let config = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let server_name = ServerName::try_from(server_name.to_string()).unwrap();
+        let connection = ClientConnection::new(Arc::new(config), server_name).unwrap();
+        let mut stream = StreamOwned::new(connection, TcpStream::connect(address).unwrap());
+
+        stream.write_all(b"GET /hello HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        let presented = stream.conn.peer_certificates().unwrap()[0].to_vec();
+        (response, presented)
+    }
+
+    #[test]
+    fn test_tls() {
+        let (default, default_der) = self_signed("localhost");
+        let (other, other_der) = self_signed("other.test");
+        let tls = TlsConfig::new(default).with_certificate("other.test", other);
+
+        let mut server = HttpServer::new();
+        server.add_route("/hello", Method::GET, || UnserializedBody(b"Hello!".to_vec()));
+        let address = server.bind_tls(("127.0.0.1", 0), tls.clone()).unwrap();
+        let _handle = server.spawn();
+
+        let trusted: &[&[u8]] = &[&default_der, &other_der];
+        let (response, presented) = get(address, "localhost", trusted);
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nHello!"));
+        assert_eq!(presented, default_der);
+
+        // The certificate is picked by the server name
+        let (_, presented) = get(address, "other.test", trusted);
+        assert_eq!(presented, other_der);
+
+        // Replaced certificates are used by new connections
+        let (reloaded, reloaded_der) = self_signed("other.test");
+        tls.set_certificate("other.test", reloaded);
+        let (response, presented) = get(address, "other.test", &[&default_der, &reloaded_der]);
+        assert!(response.ends_with("\r\n\r\nHello!"));
+        assert_eq!(presented, reloaded_der);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_tls_async() {
+        let (certificate, der) = self_signed("localhost");
+
+        let mut server = HttpServer::new();
+        server.add_route("/hello", Method::GET, || UnserializedBody(b"Hello!".to_vec()));
+        let address = server.bind_tls(("127.0.0.1", 0), TlsConfig::new(certificate)).unwrap();
+        let shutdown = server.shutdown_handle();
+        let running = tokio::spawn(async move { server.run_async().await });
+
+        let (response, _) = tokio::task::spawn_blocking(move || get(address, "localhost", &[&der])).await.unwrap();
+        assert!(response.ends_with("\r\n\r\nHello!"));
+
+        shutdown.shutdown();
+        running.await.unwrap();
+    }
+
+    #[test]
+    fn test_invalid_certificate() {
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let key = generated.key_pair.serialize_pem();
+        assert!(matches!(TlsCertificate::from_pem(b"", key.as_bytes()), Err(TlsError::NoCertificates)));
+        assert!(matches!(TlsCertificate::from_pem(generated.cert.pem().as_bytes(), b""), Err(TlsError::Pem(_))));
+
+        let other_key = rcgen::KeyPair::generate().unwrap();
+        let mismatched = TlsCertificate::from_pem(generated.cert.pem().as_bytes(), other_key.serialize_pem().as_bytes());
+        assert!(matches!(mismatched, Err(TlsError::Rustls(_))));
+    }
+}
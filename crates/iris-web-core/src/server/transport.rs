@@ -0,0 +1,105 @@
+use std::{io::{Read, Write}, net::{Shutdown, TcpStream}};
+
+/// Byte stream of a connection, either plain TCP or TLS on top of it.
+pub(crate) enum Transport {
+    Plain(TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<rustls::StreamOwned<rustls::ServerConnection, TcpStream>>),
+    /// TLS connection whose ClientHello was not read yet, clients choosing h2 with ALPN
+    /// are served on a runtime instead of getting their handshake done by the worker.
+    #[cfg(all(feature = "tls", feature = "http2"))]
+    TlsPending(TcpStream, std::sync::Arc<rustls::ServerConfig>),
+}
+
+impl Transport {
+    /// Returns the underlying TCP stream, used for socket options and timeouts.
+    pub(crate) fn tcp(&self) -> &TcpStream {
+        match self {
+            Transport::Plain(stream) => stream,
+            #[cfg(feature = "tls")]
+            Transport::Tls(stream) => &stream.sock,
+            #[cfg(all(feature = "tls", feature = "http2"))]
+            Transport::TlsPending(stream, _) => stream,
+        }
+    }
+
+    /// Returns whether the client negotiated HTTP/2 with ALPN, `None` for plain connections.
+    #[cfg(feature = "http2")]
+    pub(crate) fn negotiated_http2(&self) -> Option<bool> {
+        match self {
+            Transport::Plain(_) => None,
+            #[cfg(feature = "tls")]
+            Transport::Tls(stream) => Some(stream.conn.alpn_protocol() == Some(b"h2")),
+            #[cfg(feature = "tls")]
+            Transport::TlsPending(..) => Some(false),
+        }
+    }
+
+    /// Returns whether data was already read from the connection but not consumed yet.
+    pub(crate) fn has_buffered_data(&mut self) -> bool {
+        match self {
+            Transport::Plain(_) => false,
+            #[cfg(feature = "tls")]
+            Transport::Tls(stream) => !matches!(stream.conn.process_new_packets(), Ok(state) if state.plaintext_bytes_to_read() == 0),
+            #[cfg(all(feature = "tls", feature = "http2"))]
+            Transport::TlsPending(..) => false,
+        }
+    }
+
+    /// Closes the connection, notifying TLS clients first.
+    pub(crate) fn close(&mut self) {
+        #[cfg(feature = "tls")]
+        if let Transport::Tls(stream) = self {
+            stream.conn.send_close_notify();
+            let _ = stream.flush();
+        }
+
+        let _ = self.tcp().shutdown(Shutdown::Both);
+    }
+}
+
+impl std::fmt::Debug for Transport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Transport::Plain(stream) => f.debug_tuple("Plain").field(stream).finish(),
+            #[cfg(feature = "tls")]
+            Transport::Tls(stream) => f.debug_tuple("Tls").field(&stream.sock).finish(),
+            #[cfg(all(feature = "tls", feature = "http2"))]
+            Transport::TlsPending(stream, _) => f.debug_tuple("TlsPending").field(stream).finish(),
+        }
+    }
+}
+
+impl Read for Transport {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Transport::Plain(stream) => stream.read(buf),
+            #[cfg(feature = "tls")]
+            Transport::Tls(stream) => stream.read(buf),
+            #[cfg(all(feature = "tls", feature = "http2"))]
+            Transport::TlsPending(..) => Err(std::io::ErrorKind::NotConnected.into()),
+        }
+    }
+}
+
+impl Write for Transport {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Transport::Plain(stream) => stream.write(buf),
+            #[cfg(feature = "tls")]
+            Transport::Tls(stream) => stream.write(buf),
+            #[cfg(all(feature = "tls", feature = "http2"))]
+            Transport::TlsPending(..) => Err(std::io::ErrorKind::NotConnected.into()),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Transport::Plain(stream) => stream.flush(),
+            #[cfg(feature = "tls")]
+            Transport::Tls(stream) => stream.flush(),
+            #[cfg(all(feature = "tls", feature = "http2"))]
+            Transport::TlsPending(stream, _) => stream.flush(),
+        }
+    }
+}
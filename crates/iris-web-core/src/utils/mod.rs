@@ -1,2 +1,2 @@
-pub mod thread_pool;
+pub mod thread_pool;
 pub mod data_container;
\ No newline at end of file
@@ -1,85 +1,156 @@
-use std::thread::JoinHandle;
-use std::sync::{mpsc, Arc, Mutex};
-
-/// A thread pool that can execute closures in parallel.
-pub struct ThreadPool {
-    workers: Vec<ThreadPoolWorker>,
-    sender: mpsc::Sender<ThreadPoolJob>,
-}
-
-impl ThreadPool {
-    /// Creates a new thread pool.
-    pub fn new(size: usize) -> Self {
-        if size == 0 {
-            // Do not create a thread pool if the size is 0.
-            return Self {
-                workers: Vec::new(),
-                sender: mpsc::channel().0,
-            };
-        }
-
-        let (sender, receiver) = mpsc::channel();
-        let receiver = Arc::new(Mutex::new(receiver));
-        let mut workers = Vec::with_capacity(size);
-
-        for id in 0..size {
-            workers.push(ThreadPoolWorker::new(id, Arc::clone(&receiver)));
-        }
-
-        Self {
-            workers,
-            sender,
-        }
-    }
-
-    /// Queues a closure to be executed in the thread pool.
-    /// The closure must be `Send` and `'static`.
-    pub fn queue<F>(&self, f: F)
-    where
-        F: FnOnce() + Send + 'static,
-    {
-        self.sender.send(Box::new(f)).unwrap();
-    }
-}
-
-impl Drop for ThreadPool {
-    fn drop(&mut self) {
-        for worker in &mut self.workers {
-            #[cfg(debug_assertions)]
-            println!("Shutting down worker {}.", worker.id);
-
-            worker.thread.take().unwrap().join().unwrap();
-        }
-    }
-}
-
-/// A worker job in the thread pool.
-type ThreadPoolJob = Box<dyn FnOnce() + Send + 'static>;
-
-/// A worker thread in the thread pool.
-struct ThreadPoolWorker {
-    id: usize,
-    thread: Option<JoinHandle<()>>,
-}
-
-impl ThreadPoolWorker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<ThreadPoolJob>>>) -> Self {
-        let thread = std::thread::spawn(move || {
-            #[cfg(debug_assertions)]
-            println!("Worker {id} started.");
-            loop {
-                let job = receiver.lock().unwrap().recv().unwrap();
-
-                #[cfg(debug_assertions)]
-                println!("Worker {id} got a job; executing.");
-
-                job();
-            }
-        });
-
-        Self {
-            id,
-            thread: Some(thread),
-        }
-    }
-}
+use std::thread::JoinHandle;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A thread pool that can execute closures in parallel.
+pub struct ThreadPool {
+    workers: Vec<ThreadPoolWorker>,
+    sender: Option<mpsc::Sender<ThreadPoolJob>>,
+}
+
+impl ThreadPool {
+    /// Creates a new thread pool.
+    pub fn new(size: usize) -> Self {
+        if size == 0 {
+            // Do not create a thread pool if the size is 0.
+            return Self {
+                workers: Vec::new(),
+                sender: Some(mpsc::channel().0),
+            };
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let mut workers = Vec::with_capacity(size);
+
+        for id in 0..size {
+            workers.push(ThreadPoolWorker::new(id, Arc::clone(&receiver)));
+        }
+
+        Self {
+            workers,
+            sender: Some(sender),
+        }
+    }
+
+    /// Queues a closure to be executed in the thread pool.
+    /// The closure must be `Send` and `'static`.
+    pub fn queue<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.sender.as_ref().expect("thread pool is shut down").send(Box::new(f)).unwrap();
+    }
+
+    /// Returns whether the pool was shut down, it doesn't accept closures anymore then.
+    pub fn is_shut_down(&self) -> bool {
+        self.sender.is_none()
+    }
+
+    /// Stops accepting new closures and waits for the workers to finish the queued ones.
+    /// Workers that are still busy after `timeout` are detached.
+    /// Returns `true` if all workers finished in time.
+    pub fn shutdown(&mut self, timeout: Option<Duration>) -> bool {
+        // Closing the channel makes the workers exit once the queue is empty.
+        self.sender.take();
+
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut finished = true;
+
+        for worker in &mut self.workers {
+            let thread = match worker.thread.take() {
+                Some(thread) => thread,
+                None => continue,
+            };
+
+            if let Some(deadline) = deadline {
+                while !thread.is_finished() && Instant::now() < deadline {
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+
+                if !thread.is_finished() {
+                    #[cfg(debug_assertions)]
+                    println!("Worker {} did not finish in time.", worker.id);
+
+                    finished = false;
+                    continue;
+                }
+            }
+
+            #[cfg(debug_assertions)]
+            println!("Shutting down worker {}.", worker.id);
+
+            thread.join().unwrap();
+        }
+
+        finished
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.shutdown(None);
+    }
+}
+
+/// A worker job in the thread pool.
+type ThreadPoolJob = Box<dyn FnOnce() + Send + 'static>;
+
+/// A worker thread in the thread pool.
+struct ThreadPoolWorker {
+    id: usize,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ThreadPoolWorker {
+    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<ThreadPoolJob>>>) -> Self {
+        let thread = std::thread::spawn(move || {
+            #[cfg(debug_assertions)]
+            println!("Worker {id} started.");
+            loop {
+                // The channel is closed when the pool shuts down.
+                let job = match receiver.lock().unwrap().recv() {
+                    Ok(job) => job,
+                    Err(_) => break,
+                };
+
+                #[cfg(debug_assertions)]
+                println!("Worker {id} got a job; executing.");
+
+                // Keep the worker alive even if the job panics.
+                if std::panic::catch_unwind(std::panic::AssertUnwindSafe(job)).is_err() {
+                    #[cfg(debug_assertions)]
+                    println!("Worker {id} job panicked.");
+                }
+            }
+        });
+
+        Self {
+            id,
+            thread: Some(thread),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_shutdown_drains_queue() {
+        let mut pool = ThreadPool::new(2);
+        let counter = Arc::new(AtomicUsize::new(0));
+
+        for _ in 0..8 {
+            let counter = counter.clone();
+            pool.queue(move || {
+                std::thread::sleep(Duration::from_millis(5));
+                counter.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+
+        assert!(pool.shutdown(Some(Duration::from_secs(5))));
+        assert_eq!(counter.load(Ordering::SeqCst), 8);
+    }
+}
@@ -22,6 +22,10 @@ fn middleware_test(data: &mut PipelineData) -> Option<()> {
     None
 }
 
+async fn async_test(request: &Request) -> String {
+    format!("Hello from async controller at {}!", request.path)
+}
+
 fn router_test_body(request: &Request, body: Data<TestBody>) -> TestBody {
     TestBody {
         test: body.data.test.clone(),
@@ -42,10 +46,12 @@ struct TestModule;
 impl Module for TestModule {
     fn build(self, router: &mut Router) {
         router
-            .add_route("/body", Method::POST, router_test_body.with_middleware(json_body::<TestBody>))
+            .add_route("/body", Method::POST, router_test_body.with_middleware(json_body::<TestBody>).with_limits(RequestLimits::new().with_max_body_size(1024)))
             .add_route("/", Method::GET, router_test.with_middleware(middleware_test))
             .add_route("/count", Method::GET, router_test_count)
-            .add_route("/test", Method::GET, || "Hello Test!".to_string());
+            .add_route("/test", Method::GET, || "Hello Test!".to_string())
+            .add_route("/async", Method::GET, async_test)
+            .add_route("/stream", Method::GET, || StreamingBody::from_chunks((0..5).map(|i| format!("Chunk {i}\n"))));
     }
 }
 
@@ -54,12 +60,35 @@ struct Counter {
 }
 
 fn main() {
-    HttpServer::new()
+    let mut server = HttpServer::new();
+    server.shutdown_handle().shutdown_on_signal().unwrap();
+
+    server
+        .with_config(ServerConfig::new().with_workers(8))
         .add_data(Counter {
             count: AtomicU32::new(0),
         })
         .add_route("/", Method::GET, test)
         .add_module("/:test", TestModule)
-        .dump_routes()
-        .listen(("localhost", 8080));
+        .dump_routes();
+
+    server.bind(("127.0.0.1", 8080)).unwrap();
+    // IPv6 may be disabled, the server still runs on IPv4 then.
+    if let Err(e) = server.bind(("::1", 8080)) {
+        println!("Not listening on IPv6: {e}");
+    }
+
+    // Serve HTTPS as well when a certificate is provided.
+    if let (Ok(cert), Ok(key)) = (std::env::var("IRIS_TLS_CERT"), std::env::var("IRIS_TLS_KEY")) {
+        let tls = TlsConfig::from_pem_files(cert, key).unwrap();
+        server.bind_tls(("127.0.0.1", 8443), tls).unwrap();
+    }
+
+    // Serve on a tokio runtime instead of the worker threads when asked to.
+    if std::env::var("IRIS_ASYNC").is_ok() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(server.run_async());
+    } else {
+        server.run();
+    }
 }